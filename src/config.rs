@@ -0,0 +1,299 @@
+use std::env;
+use std::fs;
+use std::sync::OnceLock;
+
+use serde::Deserialize;
+
+use crate::embed::collections::COLLECTION_NAME;
+use crate::embed::instances::MODEL_NAME;
+
+const DEFAULT_CONFIG_PATH: &str = "vecembed.toml";
+
+static CONFIG: OnceLock<VecEmbedConfig> = OnceLock::new();
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct VecEmbedConfig {
+    // Name of the collection ingest and retrieval use, and the base name of reindexed versions
+    pub collection: String,
+    // Alias retrieval reads through; reindexing swaps it between collection versions
+    pub alias: Option<String>,
+    pub embedding: EmbeddingConfig,
+    pub collections: Vec<CollectionConfig>,
+}
+
+impl Default for VecEmbedConfig {
+    fn default() -> Self {
+        VecEmbedConfig {
+            collection: COLLECTION_NAME.to_string(),
+            alias: None,
+            embedding: EmbeddingConfig::default(),
+            collections: Vec::new(),
+        }
+    }
+}
+
+impl VecEmbedConfig {
+    pub fn active_collection(&self) -> &CollectionConfig {
+        self.collections
+            .iter()
+            .find(|collection| collection.name == self.collection)
+            .expect("Active collection is always present after loading")
+    }
+
+    pub fn read_collection_name(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.collection)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct EmbeddingConfig {
+    pub model: String,
+    // Truncate embeddings to this many dimensions; the model must be Matryoshka-trained
+    pub dimension: Option<usize>,
+    // Vector the model above is stored under once other providers are configured
+    pub vector_name: String,
+    // Input formats for the model above, see `EmbeddingProvider`
+    pub query_template: String,
+    pub document_template: String,
+    // Used by every provider when a request doesn't send a task description
+    pub task_description: String,
+    // Additional models embedded into named vectors of the same points
+    pub providers: Vec<EmbeddingProvider>,
+}
+
+impl Default for EmbeddingConfig {
+    fn default() -> Self {
+        EmbeddingConfig {
+            model: MODEL_NAME.to_string(),
+            dimension: None,
+            vector_name: "default".to_string(),
+            // gte-Qwen2 instructs queries only, documents are embedded as they are
+            query_template: "Instruct: {task}\nQuery: {query}".to_string(),
+            document_template: "{text}".to_string(),
+            task_description:
+                "Given a web search query, retrieve relevant passages that answer the query"
+                    .to_string(),
+            providers: Vec::new(),
+        }
+    }
+}
+
+impl EmbeddingConfig {
+    // A single model keeps the collection's unnamed vector, as before providers existed
+    pub fn uses_named_vectors(&self) -> bool {
+        !self.providers.is_empty()
+    }
+
+    pub fn primary_provider(&self) -> EmbeddingProvider {
+        EmbeddingProvider {
+            name: self.vector_name.clone(),
+            model: self.model.clone(),
+            dimension: self.dimension,
+            url: None,
+            api_key_env: None,
+            query_template: self.query_template.clone(),
+            document_template: self.document_template.clone(),
+        }
+    }
+
+    // Every provider whose embeddings are stored, the primary model first
+    pub fn vector_providers(&self) -> Vec<EmbeddingProvider> {
+        let mut providers = vec![self.primary_provider()];
+        providers.extend(self.providers.iter().cloned());
+
+        providers
+    }
+
+    // Looks up a provider by vector name; no name selects the primary model
+    pub fn provider(&self, vector_name: Option<&str>) -> Option<EmbeddingProvider> {
+        match vector_name {
+            None | Some("") => Some(self.primary_provider()),
+            Some(name) => self
+                .vector_providers()
+                .into_iter()
+                .find(|provider| provider.name == name),
+        }
+    }
+
+    // Name the provider's vector has in the collection
+    pub fn stored_vector_name(&self, provider: &EmbeddingProvider) -> Option<String> {
+        self.uses_named_vectors().then(|| provider.name.clone())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct EmbeddingProvider {
+    // Name of the vector its embeddings are stored under
+    pub name: String,
+    pub model: String,
+    pub dimension: Option<usize>,
+    // OpenAI-compatible endpoint; defaults to the one in OPENAI_URL
+    pub url: Option<String>,
+    // Environment variable holding the endpoint's API key
+    pub api_key_env: Option<String>,
+    // Text sent to the model for a query, with `{task}` and `{query}` filled in
+    #[serde(default = "default_query_template")]
+    pub query_template: String,
+    // Text sent to the model for a document chunk, with `{text}` filled in
+    #[serde(default = "default_document_template")]
+    pub document_template: String,
+}
+
+fn default_query_template() -> String {
+    "{query}".to_string()
+}
+
+fn default_document_template() -> String {
+    "{text}".to_string()
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DistanceMetric {
+    Cosine,
+    Dot,
+    Euclid,
+    Manhattan,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PayloadIndexType {
+    Keyword,
+    Integer,
+    Float,
+    Bool,
+    Text,
+    Datetime,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+pub struct PayloadIndex {
+    pub field: String,
+    #[serde(rename = "type")]
+    pub index_type: PayloadIndexType,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+pub struct HnswConfig {
+    pub m: Option<u64>,
+    pub ef_construct: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum QuantizationKind {
+    // int8 per dimension, 4x smaller
+    Scalar,
+    // 1 bit per dimension, 32x smaller; best suited to wide embeddings
+    Binary,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+pub struct QuantizationSettings {
+    #[serde(rename = "type")]
+    pub kind: QuantizationKind,
+    // Scalar only: quantile used to clip outliers before quantizing
+    pub quantile: Option<f32>,
+    // Keep quantized vectors in RAM even when the originals are on disk
+    pub always_ram: Option<bool>,
+    // Search defaults for requests that don't send their own quantization params
+    #[serde(default = "default_rescore")]
+    pub rescore: bool,
+    #[serde(default = "default_oversampling")]
+    pub oversampling: f64,
+}
+
+fn default_rescore() -> bool {
+    true
+}
+
+fn default_oversampling() -> f64 {
+    2.0
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct CollectionConfig {
+    pub name: String,
+    pub distance: DistanceMetric,
+    pub on_disk: bool,
+    pub memmap_threshold: Option<u64>,
+    pub indexing_threshold: Option<u64>,
+    pub hnsw: Option<HnswConfig>,
+    pub quantization: Option<QuantizationSettings>,
+    pub shard_number: Option<u32>,
+    pub replication_factor: Option<u32>,
+    pub payload_indexes: Vec<PayloadIndex>,
+    // Apply changeable settings to a live collection that disagrees with this config
+    pub migrate: bool,
+}
+
+impl Default for CollectionConfig {
+    fn default() -> Self {
+        let data_threshold: u64 = 1000 * 1000;
+
+        CollectionConfig {
+            name: COLLECTION_NAME.to_string(),
+            distance: DistanceMetric::Cosine,
+            on_disk: true,
+            memmap_threshold: Some(data_threshold / 2),
+            indexing_threshold: Some(data_threshold / 2),
+            hnsw: None,
+            quantization: None,
+            shard_number: None,
+            replication_factor: None,
+            payload_indexes: vec![
+                PayloadIndex {
+                    field: "document_id".to_string(),
+                    index_type: PayloadIndexType::Integer,
+                },
+                PayloadIndex {
+                    field: "user_id".to_string(),
+                    index_type: PayloadIndexType::Integer,
+                },
+                PayloadIndex {
+                    field: "table_name".to_string(),
+                    index_type: PayloadIndexType::Keyword,
+                },
+                PayloadIndex {
+                    field: "language".to_string(),
+                    index_type: PayloadIndexType::Keyword,
+                },
+            ],
+            migrate: false,
+        }
+    }
+}
+
+fn load_config() -> VecEmbedConfig {
+    let path = env::var("VECEMBED_CONFIG").unwrap_or(DEFAULT_CONFIG_PATH.to_string());
+
+    let mut config = match fs::read_to_string(&path) {
+        Ok(contents) => toml::from_str::<VecEmbedConfig>(&contents)
+            .unwrap_or_else(|e| panic!("Couldn't parse config file {}: {}", path, e)),
+        Err(_) => {
+            log::info!("No config file found at {}, using defaults", path);
+            VecEmbedConfig::default()
+        }
+    };
+
+    if !config
+        .collections
+        .iter()
+        .any(|collection| collection.name == config.collection)
+    {
+        config.collections.push(CollectionConfig {
+            name: config.collection.clone(),
+            ..Default::default()
+        });
+    }
+
+    config
+}
+
+pub fn get_config() -> &'static VecEmbedConfig {
+    CONFIG.get_or_init(load_config)
+}
@@ -0,0 +1,99 @@
+use chrono::{DateTime, Utc};
+use sea_orm::{
+    sea_query::{Expr, OnConflict},
+    ActiveValue::{NotSet, Set},
+    ColumnTrait, EntityTrait, QueryFilter,
+};
+
+use super::{errors::EmbeddingError, instances::get_db_instance, sync_state::{embedding_fingerprint, embedding_models}};
+use crate::entities::vecembed_import_checkpoints::{ActiveModel, Column, Entity, Model};
+
+// Where an import of a table got to, saved after every page
+pub struct ImportCheckpoint {
+    pub table_name: String,
+    pub last_id: String,
+    pub processed_rows: u64,
+    pub total_rows: u64,
+    pub started_at: DateTime<Utc>,
+}
+
+// The table's unfinished import, if there's one made with the current embedding settings
+pub async fn unfinished_checkpoint(table_name: &str) -> Result<Option<Model>, EmbeddingError> {
+    let db = get_db_instance().await;
+    let checkpoint = Entity::find()
+        .filter(Column::TableName.eq(table_name))
+        .filter(Column::CompletedAt.is_null())
+        .one(db)
+        .await?;
+
+    Ok(checkpoint.filter(|checkpoint| {
+        let fingerprint = embedding_fingerprint();
+        let same_settings = checkpoint.settings_fingerprint.as_deref() == Some(fingerprint.as_str());
+        if !same_settings {
+            log::warn!(
+                "Ignoring the checkpoint of {} made with other embedding settings",
+                table_name
+            );
+        }
+        same_settings
+    }))
+}
+
+pub async fn save_checkpoint(checkpoint: &ImportCheckpoint) -> Result<(), EmbeddingError> {
+    let db = get_db_instance().await;
+    let state = ActiveModel {
+        id: NotSet,
+        table_name: Set(checkpoint.table_name.clone()),
+        last_id: Set(checkpoint.last_id.clone()),
+        processed_rows: Set(checkpoint.processed_rows),
+        total_rows: Set(checkpoint.total_rows),
+        model: Set(embedding_models()),
+        settings_fingerprint: Set(Some(embedding_fingerprint())),
+        started_at: Set(checkpoint.started_at),
+        updated_at: Set(Utc::now()),
+        completed_at: Set(None),
+    };
+
+    Entity::insert(state)
+        .on_conflict(
+            OnConflict::column(Column::TableName)
+                .update_columns([
+                    Column::LastId,
+                    Column::ProcessedRows,
+                    Column::TotalRows,
+                    Column::Model,
+                    Column::SettingsFingerprint,
+                    Column::StartedAt,
+                    Column::UpdatedAt,
+                    Column::CompletedAt,
+                ])
+                .to_owned(),
+        )
+        .exec(db)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn complete_checkpoint(table_name: &str) -> Result<(), EmbeddingError> {
+    let db = get_db_instance().await;
+    let now = Utc::now();
+    Entity::update_many()
+        .col_expr(Column::CompletedAt, Expr::value(now))
+        .col_expr(Column::UpdatedAt, Expr::value(now))
+        .filter(Column::TableName.eq(table_name))
+        .exec(db)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn reset_checkpoint(table_name: &str) -> Result<(), EmbeddingError> {
+    let db = get_db_instance().await;
+    Entity::delete_many()
+        .filter(Column::TableName.eq(table_name))
+        .exec(db)
+        .await?;
+
+    Ok(())
+}
@@ -5,13 +5,24 @@ use std::task::{Context, Poll};
 use futures::Stream;
 
 use crate::embed::instances::get_tokenizer_instance;
+use crate::embed::language::sentence_ends;
 
 use super::errors::EmbeddingError;
 
+#[derive(Clone, Copy, Debug)]
+pub struct TextChunk<'a> {
+    pub text: &'a str,
+    pub start: usize,
+    pub end: usize,
+    pub token_count: usize,
+}
+
 pub struct StringChunkIterator<'a> {
     chunkable: &'a str,
     max_chunk_size: usize,
     start: usize,
+    // Characters a chunk is preferably cut after
+    sentence_ends: &'static [char],
 }
 
 impl<'a> StringChunkIterator<'a> {
@@ -20,9 +31,16 @@ impl<'a> StringChunkIterator<'a> {
             chunkable,
             max_chunk_size,
             start: 0,
+            sentence_ends: sentence_ends(None),
         }
     }
 
+    // Cuts chunks at the sentence ends of the text's language
+    pub fn with_language(mut self, language: Option<&str>) -> Self {
+        self.sentence_ends = sentence_ends(language);
+        self
+    }
+
     async fn get_token_count(&self, input: &str) -> Result<usize, EmbeddingError> {
         let tokenizer = get_tokenizer_instance().await;
         // Encode the text in the document
@@ -32,7 +50,7 @@ impl<'a> StringChunkIterator<'a> {
         Ok(encoding.len())
     }
 
-    pub async fn get_next_chunk(&mut self) -> Option<Result<(&'a str, usize, usize), EmbeddingError>> {
+    pub async fn get_next_chunk(&mut self) -> Option<Result<TextChunk<'a>, EmbeddingError>> {
         if self.start >= self.chunkable.len() {
             return None;
         }
@@ -60,11 +78,11 @@ impl<'a> StringChunkIterator<'a> {
             }
 
             substr = &self.chunkable[self.start..end];
-            substr = if let Some((index, _)) = substr.rmatch_indices('.').next() {
+            substr = if let Some((index, end)) = substr.rmatch_indices(self.sentence_ends).next() {
                 let max_distance = 50;
                 let char_distance = substr[index..].chars().count() - 1;
                 if char_distance <= max_distance && char_distance > 10 {
-                    &self.chunkable[self.start..self.start + index + 1]
+                    &self.chunkable[self.start..self.start + index + end.len()]
                 } else {
                     substr
                 }
@@ -81,19 +99,29 @@ impl<'a> StringChunkIterator<'a> {
             substr_size = substr_size.saturating_sub(cmp::max(distance_from_limit, 1));
         }
 
-        let new_end = self.start + substr.len();
-        let result = (substr, self.start, new_end);
-        self.start = new_end + 1;
-        while self.start < self.chunkable.len() && !self.chunkable.is_char_boundary(self.start) {
-            self.start += 1;
+        // Always move forward, even if not a single character fits the limit
+        if substr.is_empty() {
+            let next_char = self.chunkable[self.start..].chars().next().map_or(1, char::len_utf8);
+            substr = &self.chunkable[self.start..self.start + next_char];
         }
 
+        // The next chunk starts right where this one ends, so chunks cover the text
+        // without gaps and their offsets line up with it
+        let new_end = self.start + substr.len();
+        let result = TextChunk {
+            text: substr,
+            start: self.start,
+            end: new_end,
+            token_count,
+        };
+        self.start = new_end;
+
         Some(Ok(result))
     }
 }
 
 impl<'a> Stream for StringChunkIterator<'a> {
-    type Item = Result<(&'a str, usize, usize), EmbeddingError>;
+    type Item = Result<TextChunk<'a>, EmbeddingError>;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         let fut = self.get_next_chunk();
@@ -101,3 +129,65 @@ impl<'a> Stream for StringChunkIterator<'a> {
         futures::future::Future::poll(fut.as_mut(), cx)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::embed::instances::use_word_tokenizer;
+
+    async fn chunks(text: &str, max_chunk_size: usize) -> Vec<TextChunk<'_>> {
+        use_word_tokenizer().await;
+
+        let mut iterator = StringChunkIterator::new(text, max_chunk_size);
+        let mut chunks = Vec::new();
+        while let Some(chunk) = iterator.get_next_chunk().await {
+            chunks.push(chunk.unwrap());
+        }
+
+        chunks
+    }
+
+    fn assert_covers(text: &str, chunks: &[TextChunk]) {
+        assert_eq!(chunks.first().map(|chunk| chunk.start), Some(0));
+        assert_eq!(chunks.last().map(|chunk| chunk.end), Some(text.len()));
+        for pair in chunks.windows(2) {
+            assert_eq!(pair[0].end, pair[1].start);
+        }
+        for chunk in chunks {
+            assert_eq!(chunk.text, &text[chunk.start..chunk.end]);
+        }
+    }
+
+    #[tokio::test]
+    async fn chunks_cover_the_text_without_gaps() {
+        let text = (0..200)
+            .map(|i| format!("word{}", i))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let chunks = chunks(&text, 20).await;
+
+        assert!(chunks.len() > 1);
+        assert_covers(&text, &chunks);
+        assert!(chunks.iter().all(|chunk| chunk.token_count <= 20));
+    }
+
+    #[tokio::test]
+    async fn chunk_offsets_stay_on_character_boundaries() {
+        let text = "héllo wörld ünïcödé 日本語のテキスト ".repeat(30);
+
+        let chunks = chunks(&text, 8).await;
+
+        assert!(chunks.len() > 1);
+        assert_covers(&text, &chunks);
+    }
+
+    #[tokio::test]
+    async fn a_single_chunk_holds_a_short_text() {
+        let chunks = chunks("Just a few words.", 20).await;
+
+        assert_eq!(chunks.len(), 1);
+        assert_eq!((chunks[0].start, chunks[0].end), (0, 17));
+        assert_eq!(chunks[0].token_count, 4);
+    }
+}
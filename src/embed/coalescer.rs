@@ -0,0 +1,344 @@
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use futures::future::{join_all, BoxFuture, FutureExt};
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::Instant;
+
+use super::{
+    embedder::{batch_token_limit, embed_batch},
+    errors::EmbeddingError,
+    retry::is_retryable,
+};
+use crate::config::{get_config, EmbeddingProvider};
+
+const COALESCE_WINDOW_MS: u64 = 5;
+
+static COALESCERS: OnceLock<HashMap<String, mpsc::UnboundedSender<EmbedRequest>>> = OnceLock::new();
+
+type EmbedReply = oneshot::Sender<Result<Vec<Vec<f32>>, EmbeddingError>>;
+
+// Embeds one batch of texts against the coalescer's provider
+type EmbedFn = Arc<
+    dyn Fn(Vec<String>, Vec<usize>) -> BoxFuture<'static, Result<Vec<Vec<f32>>, EmbeddingError>>
+        + Send
+        + Sync,
+>;
+
+struct EmbedRequest {
+    texts: Vec<String>,
+    token_counts: Vec<usize>,
+    reply: EmbedReply,
+}
+
+impl EmbedRequest {
+    fn tokens(&self) -> usize {
+        self.token_counts.iter().sum()
+    }
+}
+
+fn coalesce_window() -> Duration {
+    Duration::from_millis(
+        std::env::var("COALESCE_WINDOW_MS")
+            .ok()
+            .and_then(|s| s.parse::<u64>().ok())
+            .unwrap_or(COALESCE_WINDOW_MS),
+    )
+}
+
+// One loop per provider gathers the requests arriving within the window after the
+// first one into a single embedding batch, up to the batch token limit
+async fn run_coalescer(embed: EmbedFn, mut requests: mpsc::UnboundedReceiver<EmbedRequest>) {
+    let mut carried: Option<EmbedRequest> = None;
+
+    loop {
+        let first = match carried.take() {
+            Some(request) => request,
+            None => match requests.recv().await {
+                Some(request) => request,
+                None => return,
+            },
+        };
+
+        let deadline = Instant::now() + coalesce_window();
+        let max_tokens = batch_token_limit();
+        let mut tokens = first.tokens();
+        let mut batch = vec![first];
+
+        while tokens < max_tokens {
+            match tokio::time::timeout_at(deadline, requests.recv()).await {
+                Ok(Some(request)) if tokens + request.tokens() > max_tokens => {
+                    carried = Some(request);
+                    break;
+                }
+                Ok(Some(request)) => {
+                    tokens += request.tokens();
+                    batch.push(request);
+                }
+                _ => break,
+            }
+        }
+
+        tokio::spawn(embed_requests(embed.clone(), batch));
+    }
+}
+
+async fn embed_requests(embed: EmbedFn, mut batch: Vec<EmbedRequest>) {
+    if batch.len() == 1 {
+        let request = batch.remove(0);
+        let result = embed(request.texts, request.token_counts).await;
+        let _ = request.reply.send(result);
+        return;
+    }
+
+    let texts = batch
+        .iter()
+        .flat_map(|request| request.texts.iter().cloned())
+        .collect::<Vec<_>>();
+    let token_counts = batch
+        .iter()
+        .flat_map(|request| request.token_counts.iter().copied())
+        .collect::<Vec<_>>();
+
+    // One embedding per text is checked by the embedder, so the split lines up
+    match embed(texts, token_counts).await {
+        Ok(mut embeddings) => {
+            for request in batch {
+                let rest = embeddings.split_off(request.texts.len());
+                // The caller may have given up already
+                let _ = request.reply.send(Ok(embeddings));
+                embeddings = rest;
+            }
+        }
+        // The server is down or overloaded, and already retried: embedding each caller's
+        // chunks on their own would only add load, so they all get the error
+        Err(err) if is_retryable(&err) => {
+            let message = err.to_string();
+            for request in batch {
+                let _ = request
+                    .reply
+                    .send(Err(EmbeddingError::BackendUnavailable(message.clone())));
+            }
+        }
+        Err(err) => {
+            // Embed each caller's chunks on their own, so one bad request doesn't fail the
+            // rest and every caller gets its own error
+            log::warn!(
+                "Coalesced embedding batch of {} requests failed: {}; retrying them separately",
+                batch.len(),
+                err
+            );
+            join_all(batch.into_iter().map(|request| {
+                let embed = &embed;
+                async move {
+                    let result = embed(request.texts, request.token_counts).await;
+                    let _ = request.reply.send(result);
+                }
+            }))
+            .await;
+        }
+    }
+}
+
+fn coalescer(provider: &EmbeddingProvider) -> Option<&'static mpsc::UnboundedSender<EmbedRequest>> {
+    COALESCERS
+        .get_or_init(|| {
+            get_config()
+                .embedding
+                .vector_providers()
+                .into_iter()
+                .map(|provider| {
+                    let (sender, receiver) = mpsc::unbounded_channel();
+                    let name = provider.name.clone();
+                    let embed: EmbedFn = Arc::new(move |texts, token_counts| {
+                        let provider = provider.clone();
+                        async move { embed_batch(&provider, texts, token_counts).await }.boxed()
+                    });
+                    tokio::spawn(run_coalescer(embed, receiver));
+
+                    (name, sender)
+                })
+                .collect()
+        })
+        .get(&provider.name)
+}
+
+// Embeds an ingest batch together with batches other requests send at the same time.
+// Batches that already fill the token budget, and any when the window is 0, go out directly.
+pub async fn embed_batch_coalesced(
+    provider: &EmbeddingProvider,
+    texts: Vec<String>,
+    token_counts: Vec<usize>,
+) -> Result<Vec<Vec<f32>>, EmbeddingError> {
+    let tokens = token_counts.iter().sum::<usize>();
+    let sender = match coalescer(provider) {
+        Some(sender) if !coalesce_window().is_zero() && tokens < batch_token_limit() => sender,
+        _ => return embed_batch(provider, texts, token_counts).await,
+    };
+
+    let (reply, response) = oneshot::channel();
+    sender
+        .send(EmbedRequest {
+            texts,
+            token_counts,
+            reply,
+        })
+        .map_err(|_| {
+            EmbeddingError::BackendUnavailable("embedding coalescer stopped".to_string())
+        })?;
+
+    response.await.map_err(|_| {
+        EmbeddingError::BackendUnavailable("embedding coalescer dropped the request".to_string())
+    })?
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use async_openai::error::{ApiError, OpenAIError};
+
+    use super::*;
+
+    type EmbedResponse = oneshot::Receiver<Result<Vec<Vec<f32>>, EmbeddingError>>;
+
+    type CoalescedError = fn(usize) -> EmbeddingError;
+
+    fn count_mismatch(texts: usize) -> EmbeddingError {
+        EmbeddingError::EmbeddingCountMismatch {
+            expected: texts,
+            actual: texts - 1,
+        }
+    }
+
+    // A 5xx response, as async-openai reports it
+    fn server_error(_: usize) -> EmbeddingError {
+        EmbeddingError::OpenAIError(OpenAIError::ApiError(ApiError {
+            message: "overloaded".to_string(),
+            r#type: None,
+            param: None,
+            code: None,
+        }))
+    }
+
+    // Embeds every text as its own number and records the batches it was sent. Batches
+    // of more than one text fail with `coalesced_error`, if it's given
+    fn fake_embed(coalesced_error: Option<CoalescedError>) -> (EmbedFn, Arc<Mutex<Vec<Vec<String>>>>) {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let recorded = calls.clone();
+        let embed: EmbedFn = Arc::new(move |texts: Vec<String>, _| {
+            recorded.lock().unwrap().push(texts.clone());
+            async move {
+                if let Some(error) = coalesced_error.filter(|_| texts.len() > 1) {
+                    return Err(error(texts.len()));
+                }
+                Ok(texts
+                    .iter()
+                    .map(|text| vec![text.parse::<f32>().unwrap()])
+                    .collect())
+            }
+            .boxed()
+        });
+
+        (embed, calls)
+    }
+
+    fn request(texts: &[&str], tokens: usize) -> (EmbedRequest, EmbedResponse) {
+        let (reply, response) = oneshot::channel();
+        let request = EmbedRequest {
+            texts: texts.iter().map(|text| text.to_string()).collect(),
+            token_counts: vec![tokens / texts.len(); texts.len()],
+            reply,
+        };
+
+        (request, response)
+    }
+
+    fn sorted(calls: &Mutex<Vec<Vec<String>>>) -> Vec<Vec<String>> {
+        let mut calls = calls.lock().unwrap().clone();
+        calls.sort();
+        calls
+    }
+
+    #[tokio::test]
+    async fn coalesced_embeddings_go_back_to_their_callers() {
+        let (embed, calls) = fake_embed(None);
+        let (first, first_response) = request(&["1", "2"], 2);
+        let (second, second_response) = request(&["3"], 1);
+        let (third, third_response) = request(&["4", "5", "6"], 3);
+
+        embed_requests(embed, vec![first, second, third]).await;
+
+        assert_eq!(calls.lock().unwrap().len(), 1);
+        assert_eq!(
+            first_response.await.unwrap().unwrap(),
+            vec![vec![1.0], vec![2.0]]
+        );
+        assert_eq!(second_response.await.unwrap().unwrap(), vec![vec![3.0]]);
+        assert_eq!(
+            third_response.await.unwrap().unwrap(),
+            vec![vec![4.0], vec![5.0], vec![6.0]]
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_coalesced_batch_is_embedded_per_caller() {
+        let (embed, calls) = fake_embed(Some(count_mismatch));
+        let (first, first_response) = request(&["1"], 1);
+        let (second, second_response) = request(&["2", "3"], 2);
+
+        embed_requests(embed, vec![first, second]).await;
+
+        assert_eq!(first_response.await.unwrap().unwrap(), vec![vec![1.0]]);
+        assert!(matches!(
+            second_response.await.unwrap(),
+            Err(EmbeddingError::EmbeddingCountMismatch {
+                expected: 2,
+                actual: 1
+            })
+        ));
+        assert_eq!(
+            sorted(&calls),
+            vec![vec!["1"], vec!["1", "2", "3"], vec!["2", "3"]]
+        );
+    }
+
+    #[tokio::test]
+    async fn retryable_failure_of_a_coalesced_batch_goes_to_every_caller() {
+        let (embed, calls) = fake_embed(Some(server_error));
+        let (first, first_response) = request(&["1"], 1);
+        let (second, second_response) = request(&["2", "3"], 2);
+
+        embed_requests(embed, vec![first, second]).await;
+
+        for response in [first_response, second_response] {
+            assert!(matches!(
+                response.await.unwrap(),
+                Err(EmbeddingError::BackendUnavailable(message)) if message.contains("overloaded")
+            ));
+        }
+        assert_eq!(sorted(&calls), vec![vec!["1", "2", "3"]]);
+    }
+
+    #[tokio::test]
+    async fn request_over_the_token_limit_is_carried_into_the_next_batch() {
+        let (embed, calls) = fake_embed(None);
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let half = batch_token_limit() / 2 + 1;
+
+        let (first, first_response) = request(&["1"], half);
+        let (second, second_response) = request(&["2"], half);
+        let (third, third_response) = request(&["3"], 1);
+        // Queued before the loop starts, so the window can't split them up
+        for request in [first, second, third] {
+            sender.send(request).unwrap();
+        }
+        tokio::spawn(run_coalescer(embed, receiver));
+
+        assert_eq!(first_response.await.unwrap().unwrap(), vec![vec![1.0]]);
+        assert_eq!(second_response.await.unwrap().unwrap(), vec![vec![2.0]]);
+        assert_eq!(third_response.await.unwrap().unwrap(), vec![vec![3.0]]);
+        assert_eq!(sorted(&calls), vec![vec!["1"], vec!["2", "3"]]);
+    }
+}
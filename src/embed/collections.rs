@@ -1,101 +1,58 @@
 use std::fmt;
 
-use sea_orm::{ColumnTrait, EntityTrait};
-
-use crate::entities::{contents, uploaded_files};
+use crate::config::{get_config, CollectionConfig};
+use crate::embed::errors::EmbeddingError;
+use crate::embed::instances::get_vector_store_instance;
 use crate::grpc::server::vecembed_rpc::EmbeddableModel;
 
 pub const COLLECTION_NAME: &str = "silatus_documents";
 
-pub trait EmbeddableMarker {}
-
-#[allow(dead_code)]
-pub trait EmbeddableEntity<E>
-where
-    E: EntityTrait + Send + Sync,
-{
-}
+// Collection ingest writes to: the alias target once an alias has been set up
+pub async fn write_collection() -> Result<CollectionConfig, EmbeddingError> {
+    let config = get_config();
+    let mut collection = config.active_collection().clone();
 
-#[allow(dead_code)]
-pub trait EmbeddableEntityColumn<E, C>
-where
-    E: EntityTrait + Send + Sync,
-    C: ColumnTrait,
-{
-    type OrderByColumnType: sea_orm::query::IntoSimpleExpr;
+    if let Some(alias) = &config.alias {
+        if let Some(target) = get_vector_store_instance().await.resolve_alias(alias).await? {
+            collection.name = target;
+        }
+    }
 
-    fn order_by_column() -> Self::OrderByColumnType;
-    fn primary_key_column() -> C;
-    fn user_id_column() -> Option<C>;
-    fn text_column() -> C;
-    fn updated_at_column() -> C;
-    fn qdrant_sync_column() -> C;
+    Ok(collection)
 }
 
-macro_rules! embeddable_entity {
-    ($entity:ty, $column:ty, $primary_key:expr, $user_id:expr, $order_by:expr, $text_column:expr, $updated_at_column:expr, $qdrant_sync_column:expr) => {
-        impl EmbeddableMarker for $entity {}
+// Points a configured alias at the base collection the first time both can exist
+pub async fn ensure_alias() -> Result<(), EmbeddingError> {
+    let config = get_config();
+    let store = get_vector_store_instance().await;
 
-        impl EmbeddableEntity<$entity> for $entity where
-            $entity: sea_orm::EntityTrait + Send + Sync + EmbeddableMarker
+    if let Some(alias) = &config.alias {
+        if store.resolve_alias(alias).await?.is_none()
+            && store.collection_exists(&config.collection).await?
         {
+            log::info!("Creating alias {} for collection {}", alias, config.collection);
+            store.switch_alias(alias, &config.collection).await?;
         }
+    }
 
-        impl EmbeddableEntityColumn<$entity, $column> for $entity
-        where
-            $entity: sea_orm::EntityTrait + Send + Sync + EmbeddableMarker,
-            $column: sea_orm::ColumnTrait,
-        {
-            type OrderByColumnType = <$entity as sea_orm::EntityTrait>::Column;
-
-            fn order_by_column() -> Self::OrderByColumnType {
-                $order_by
-            }
-
-            fn primary_key_column() -> <$entity as sea_orm::EntityTrait>::Column {
-                $primary_key
-            }
-
-            fn user_id_column() -> Option<<$entity as sea_orm::EntityTrait>::Column> {
-                $user_id
-            }
-
-            fn text_column() -> <$entity as sea_orm::EntityTrait>::Column {
-                $text_column
-            }
+    Ok(())
+}
 
-            fn updated_at_column() -> <$entity as sea_orm::EntityTrait>::Column {
-                $updated_at_column
-            }
+// Brings every configured collection in line with the config file
+pub async fn sync_collections() -> Result<(), EmbeddingError> {
+    let store = get_vector_store_instance().await;
+    let active_collection = write_collection().await?;
 
-            fn qdrant_sync_column() -> <$entity as sea_orm::EntityTrait>::Column {
-                $qdrant_sync_column
-            }
+    for collection in &get_config().collections {
+        if collection.name == get_config().collection {
+            store.sync_collection(&active_collection).await?;
+        } else {
+            store.sync_collection(collection).await?;
         }
-    };
-}
-
-embeddable_entity!(
-    contents::Entity,
-    contents::Column,
-    contents::Column::Id,
-    None,
-    contents::Column::Id,
-    contents::Column::Body,
-    contents::Column::UpdatedAt,
-    contents::Column::QdrantSyncAt
-);
+    }
 
-embeddable_entity!(
-    uploaded_files::Entity,
-    uploaded_files::Column,
-    uploaded_files::Column::Id,
-    Some(uploaded_files::Column::UserId),
-    uploaded_files::Column::Id,
-    uploaded_files::Column::Text,
-    uploaded_files::Column::UpdatedAt,
-    uploaded_files::Column::QdrantSyncAt
-);
+    ensure_alias().await
+}
 
 impl fmt::Display for EmbeddableModel {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -1,389 +1,890 @@
 use std::{
     collections::HashMap,
+    future::Future,
     sync::Arc,
 };
-use std::sync::atomic::{AtomicBool, Ordering};
+use chrono::{DateTime, Utc};
 use tokio::sync::Mutex;
 
-use chrono::{DateTime, Utc};
 use futures::stream::{StreamExt};
-use qdrant_client::{
-    client::Payload,
-    qdrant::{
-        vectors_config::Config, Condition, CreateCollection,
-        Distance, FieldType, Filter, PointStruct, VectorsConfig, CreateFieldIndexCollection, UpsertPoints, DeletePoints,
-        VectorParams, PointsSelector, points_selector::PointsSelectorOneOf,
-    },
-};
 
-use async_openai::{types::CreateEmbeddingRequestArgs};
-use futures::future::join_all;
-use qdrant_client::qdrant::{CreateFieldIndexCollectionBuilder, OptimizersConfigDiff};
-use sea_orm::{ConnectionTrait, DatabaseBackend, Statement};
+use futures::future::try_join_all;
 
 use super::{
-    errors::{EmbeddingError, QdrantClientError},
-    instances::{get_qdrant_instance},
+    errors::EmbeddingError,
+    instances::get_vector_store_instance,
+    store::{PointVectors, StorePayload, VectorPoint, VectorSpec},
 };
 
+use crate::config::{get_config, CollectionConfig};
 use crate::embed::{
-    chunk_strings::StringChunkIterator,
-    collections::COLLECTION_NAME,
-    instances::{self, get_db_instance},
+    chunk_strings::{StringChunkIterator, TextChunk},
+    coalescer::embed_batch_coalesced,
+    collections::{ensure_alias, write_collection},
+    document_text::{document_key, load_frames, DocumentFrame, DocumentKey},
+    duplicates::{find_duplicates, save_bands, MinHash, MinHasher},
+    embedder::{batch_token_limit, format_document},
+    extract::{clean_documents, extract_file_texts, page_at, CleanedText, ExtractedDocuments},
+    language::{chunk_language, detect_language},
+    structure::TextStructure,
+    sync_state::{
+        content_hash, mark_synced, record_failure, skip_unchanged, ContentHasher, SyncedDocument,
+    },
+    tables::{embeddable_table, DuplicateHandling},
 };
-use crate::embed::instances::get_embedding_client_instance;
 use crate::grpc::server::vecembed_rpc::VectorDbDocument;
 
 const MAX_DOCUMENT_BATCH_SIZE: usize = 50;
-const MAX_CHUNK_TEXT_LENGTH: usize = 25000;
+const MAX_CHUNK_TOKENS: usize = 8192;
+// Chunk text buffered across documents before it's embedded and stored
+const MAX_PENDING_CHUNK_TEXT: usize = 250000;
 const MAX_TEXT_CHUNK_BATCH_SIZE: usize = 64;
 
-fn chunks_to_points(
-    chunks: Vec<(Vec<f32>, usize, usize)>,
-    payload: HashMap<&str, serde_json::Value>,
-) -> Vec<PointStruct> {
-    chunks
-        .into_iter()
-        .map(|(embedding, start, end)| {
-            let mut final_payload_hashmap = HashMap::new();
-            final_payload_hashmap.insert("start", serde_json::Value::from(start));
-            final_payload_hashmap.insert("end", serde_json::Value::from(end));
+// Tokens of the text column each chunk can hold; the template's text takes its share
+pub fn chunk_token_budget(frame: Option<&DocumentFrame>) -> usize {
+    MAX_CHUNK_TOKENS - frame.map_or(0, |frame| frame.token_count).min(MAX_CHUNK_TOKENS / 2)
+}
 
-            final_payload_hashmap.extend(payload.clone());
+pub struct IngestTarget {
+    pub collection: CollectionConfig,
+    // Record the sync on the source row; reindexing into a new collection leaves it alone
+    pub mark_synced: bool,
+    // Leave out documents embedded from the same text before, which only holds when
+    // that embedding went into this collection
+    pub skip_unchanged: bool,
+}
 
-            // Combine hashmaps
-            let json_payload: serde_json::Value =
-                serde_json::to_value(final_payload_hashmap).unwrap();
+// A chunk waiting to be embedded, with the document it came from
+struct PendingChunk<'a> {
+    document: &'a VectorDbDocument,
+    frame: Option<&'a DocumentFrame>,
+    // Set when the chunk is of the document's cleaned HTML rather than its content
+    cleaned: Option<&'a CleanedText>,
+    structure: &'a TextStructure,
+    // Position of the chunk among its document's chunks
+    index: u32,
+    language: Option<&'static str>,
+    // Row of the same table the document nearly duplicates
+    duplicate_of: Option<i64>,
+    chunk: TextChunk<'a>,
+}
 
-            // Convert to Qdrant payload
-            let payload: Payload = json_payload.try_into().unwrap();
+impl PendingChunk<'_> {
+    // The text that's embedded, the chunk inside its table's text template
+    fn text(&self) -> String {
+        match self.frame {
+            Some(frame) => frame.wrap(self.chunk.text),
+            None => self.chunk.text.to_string(),
+        }
+    }
 
-            PointStruct::new(uuid::Uuid::now_v7().to_string(), embedding, payload)
-        })
-        .collect::<Vec<PointStruct>>()
+    fn token_count(&self) -> usize {
+        self.chunk.token_count + self.frame.map_or(0, |frame| frame.token_count)
+    }
 }
 
-async fn process_chunks(
-    id: i64,
-    table_name: &str,
-    user_id: Option<u64>,
-    chunks: Vec<(&str, usize, usize)>,
-) -> Result<(), EmbeddingError> {
-    let filtered_chunks: Vec<(&str, usize, usize)> = chunks
-        .into_iter()
-        .filter(|(s, _, _)| !s.is_empty())
-        .collect();
+fn chunk_to_point(
+    pending: &PendingChunk,
+    vectors: PointVectors,
+    payload: &StorePayload,
+) -> VectorPoint {
+    // Offsets are always into the document's content
+    let (start, end) = match pending.cleaned {
+        Some(cleaned) => (
+            cleaned.body_start(pending.chunk.start),
+            cleaned.body_end(pending.chunk.end),
+        ),
+        None => (pending.chunk.start, pending.chunk.end),
+    };
+
+    let mut final_payload_hashmap = HashMap::new();
+    final_payload_hashmap.insert("start".to_string(), serde_json::Value::from(start));
+    final_payload_hashmap.insert("end".to_string(), serde_json::Value::from(end));
+    final_payload_hashmap.insert(
+        "table_name".to_string(),
+        serde_json::Value::from(pending.document.table_name.clone()),
+    );
+    final_payload_hashmap.insert("document_id".to_string(), serde_json::Value::from(pending.document.id));
+    if let Some(user_id) = pending.document.user_id {
+        final_payload_hashmap.insert("user_id".to_string(), serde_json::Value::from(user_id));
+    }
+    let page_offsets = &pending.document.page_offsets;
+    if !page_offsets.is_empty() {
+        final_payload_hashmap.insert(
+            "page".to_string(),
+            serde_json::Value::from(page_at(page_offsets, start)),
+        );
+        final_payload_hashmap.insert(
+            "page_end".to_string(),
+            serde_json::Value::from(page_at(page_offsets, end.saturating_sub(1))),
+        );
+    }
+    if let Some(language) = pending.language {
+        final_payload_hashmap.insert("language".to_string(), serde_json::Value::from(language));
+    }
+    if let Some(duplicate_of) = pending.duplicate_of {
+        final_payload_hashmap.insert("duplicate_of".to_string(), serde_json::Value::from(duplicate_of));
+    }
+    if pending.cleaned.is_some() {
+        final_payload_hashmap.insert("html_cleaned".to_string(), serde_json::Value::from(true));
+    }
+    final_payload_hashmap.insert("chunk_index".to_string(), serde_json::Value::from(pending.index));
+    final_payload_hashmap.insert(
+        "token_count".to_string(),
+        serde_json::Value::from(pending.token_count()),
+    );
+    final_payload_hashmap.insert(
+        "paragraph".to_string(),
+        serde_json::Value::from(pending.structure.paragraph_index(pending.chunk.start)),
+    );
+    let heading_path = pending.structure.heading_path(pending.chunk.start);
+    if !heading_path.is_empty() {
+        final_payload_hashmap.insert(
+            "heading_path".to_string(),
+            serde_json::Value::from(heading_path.to_vec()),
+        );
+    }
+
+    final_payload_hashmap.extend(payload.clone());
 
-    let embedding_client = Arc::new(get_embedding_client_instance().await);
+    VectorPoint {
+        id: uuid::Uuid::now_v7().to_string(),
+        vectors,
+        payload: final_payload_hashmap,
+    }
+}
 
-    let chunk_embeddings = Arc::new(Mutex::new(Vec::new()));
+// Fills each batch up to the token budget in chunk order, regardless of which document
+// a chunk belongs to. A chunk over the budget on its own gets a batch to itself.
+fn pack_batches<'a, 'b>(
+    chunks: &'b [PendingChunk<'a>],
+    max_tokens: usize,
+    max_chunks: usize,
+) -> Vec<&'b [PendingChunk<'a>]> {
+    let mut batches = Vec::new();
+    let mut start = 0;
+    let mut tokens = 0;
+
+    for (index, pending) in chunks.iter().enumerate() {
+        let token_count = pending.token_count();
+        if index > start && (tokens + token_count > max_tokens || index - start >= max_chunks) {
+            batches.push(&chunks[start..index]);
+            start = index;
+            tokens = 0;
+        }
+        tokens += token_count;
+    }
+    if start < chunks.len() {
+        batches.push(&chunks[start..]);
+    }
 
+    batches
+}
+
+async fn process_chunks(
+    chunks: &[PendingChunk<'_>],
+    target: &IngestTarget,
+) -> Result<(), EmbeddingError> {
     let max_text_chunk_batch_size = std::env::var("MAX_TEXT_CHUNK_BATCH_SIZE")
         .ok()
         .and_then(|s| s.parse::<usize>().ok())
         .unwrap_or(MAX_TEXT_CHUNK_BATCH_SIZE);
 
-    let tasks = filtered_chunks.chunks(max_text_chunk_batch_size)
-        .map(|chunk| {
-            let embedding_client = Arc::clone(&embedding_client);
-            let chunk_embeddings = Arc::clone(&chunk_embeddings);
+    let max_batch_tokens = batch_token_limit();
 
-            async move {
-                let chunk_strings: Vec<String> = chunk
-                    .iter()
-                    .map(|(s, _, _)| s.to_string())
-                    .collect();
-
-                let request = CreateEmbeddingRequestArgs::default()
-                    .model("silatus/gte-Qwen2-7B-instruct-INT4")
-                    .input(chunk_strings)
-                    .build()?;
+    let embedding_config = &get_config().embedding;
+    let providers = embedding_config.vector_providers();
 
-                let response = embedding_client.embeddings().create(request).await?;
+    let tasks = pack_batches(chunks, max_batch_tokens, max_text_chunk_batch_size)
+        .into_iter()
+        .map(|batch| {
+            let providers = &providers;
 
-                let batch_embeddings: Vec<(Vec<f32>, usize, usize)> = chunk.iter()
-                    .zip(response.data.iter())
-                    .map(|((_, start, end), data)| (data.embedding.clone(), *start, *end))
+            async move {
+                let token_counts: Vec<usize> = batch
+                    .iter()
+                    .map(|pending| pending.token_count())
                     .collect();
 
-                let mut chunk_embeddings = chunk_embeddings.lock().await;
-                chunk_embeddings.extend(batch_embeddings);
+                // Every configured provider embeds the same chunks into its own vector,
+                // each formatted the way its model expects documents
+                let provider_embeddings = try_join_all(providers.iter().map(|provider| {
+                    let chunk_strings: Vec<String> = batch
+                        .iter()
+                        .map(|pending| format_document(provider, &pending.text()))
+                        .collect();
+
+                    embed_batch_coalesced(provider, chunk_strings, token_counts.clone())
+                }))
+                .await?;
+
+                let mut batch_vectors = vec![PointVectors::new(); batch.len()];
+                for (provider, embeddings) in providers.iter().zip(provider_embeddings) {
+                    let vector_name = embedding_config.stored_vector_name(provider);
+                    for (vectors, embedding) in batch_vectors.iter_mut().zip(embeddings) {
+                        vectors.insert(vector_name.clone(), embedding);
+                    }
+                }
 
-                Ok::<_, EmbeddingError>(())
+                Ok::<_, EmbeddingError>(batch_vectors)
             }
         })
         .collect::<Vec<_>>();
 
-    // Execute all tasks concurrently
-    let results = join_all(tasks).await;
-
-    // Handle any errors
-    for result in results {
-        result?;
-    }
-
-    // Return the final result
-    let chunk_embeddings = Arc::try_unwrap(chunk_embeddings)
-        .expect("Mutex should be unwrapped")
-        .into_inner();
-
+    // Execute all batches concurrently; the embedding limiter bounds how many run at once
+    let chunk_vectors: Vec<PointVectors> = try_join_all(tasks).await?.into_iter().flatten().collect();
 
-    let client = get_qdrant_instance().await;
+    let store = get_vector_store_instance().await;
 
     // Process each document in the batch
-    let embedding_size: u64 = chunk_embeddings[0].0.len() as u64;
+    let vector_specs: Vec<VectorSpec> = providers
+        .iter()
+        .map(|provider| {
+            let name = embedding_config.stored_vector_name(provider);
+            let dimension = chunk_vectors[0][&name].len() as u64;
 
-    // Create Qdrant collection if one doesn't already exist
-    let collection_exists = client
-        .collection_exists(COLLECTION_NAME)
-        .await
-        .map_err(QdrantClientError::from)?;
-
-    if !collection_exists {
-        let data_threshold: u64 = 1 * 1000 * 1000;
-
-        let create_collection_result = client
-            .create_collection(
-                CreateCollection {
-                    collection_name: COLLECTION_NAME.to_string(),
-                    vectors_config: Some(VectorsConfig {
-                        config: Some(Config::Params(VectorParams {
-                            size: embedding_size,
-                            on_disk: Some(true),
-                            distance: Distance::Cosine.into(),
-                            ..Default::default()
-                        })),
-                    }),
-                    optimizers_config: Some(OptimizersConfigDiff {
-                        memmap_threshold: Some(data_threshold / 2),
-                        indexing_threshold: Some(data_threshold / 2),
-                        ..Default::default()
-                    }),
-                    ..Default::default()
-                }
-            )
-            .await
-            .map_err(QdrantClientError::from);
-
-        // Handle specific error for existing collection
-        if let Err(e) = create_collection_result {
-            match e {
-                QdrantClientError::ClientError(ref msg) if msg.contains("already exists") => {
-                    // Ignore the error and continue if the collection already exists
-                }
-                _ => return Err(EmbeddingError::from(e)), // Propagate other errors
+            VectorSpec { name, dimension }
+        })
+        .collect();
+    let embedding_size = vector_specs[0].dimension;
+
+    // Create the collection if one doesn't already exist
+    let collection = &target.collection;
+    let collection_vectors = store.ensure_collection(collection, &vector_specs).await?;
+
+    // Vectors of different sizes can't be compared, so never mix them in one collection
+    for spec in &vector_specs {
+        match collection_vectors.iter().find(|live| live.name == spec.name) {
+            Some(live) if live.dimension == spec.dimension => {}
+            Some(live) => {
+                return Err(EmbeddingError::DimensionMismatch {
+                    collection: collection.name.clone(),
+                    expected: live.dimension,
+                    actual: spec.dimension,
+                })
+            }
+            None => {
+                return Err(EmbeddingError::MissingVector {
+                    collection: collection.name.clone(),
+                    vector: spec.name.clone().unwrap_or_default(),
+                })
             }
         }
-
-        // Create indexes
-        client
-            .create_field_index(
-                CreateFieldIndexCollectionBuilder::new(
-                    COLLECTION_NAME,
-                    "document_id",
-                    FieldType::Integer,
-            ).wait(true)
-            )
-            .await
-            .map_err(QdrantClientError::from)?;
-
-        client
-            .create_field_index(
-                CreateFieldIndexCollectionBuilder::new(
-                    COLLECTION_NAME,
-                    "user_id",
-                    FieldType::Integer,
-                ).wait(true)
-            )
-            .await
-            .map_err(QdrantClientError::from)?;
-
-        client
-            .create_field_index(
-                CreateFieldIndexCollectionBuilder::new(
-                    COLLECTION_NAME,
-                    "table_name",
-                    FieldType::Keyword,
-                ).wait(true),
-            )
-            .await
-            .map_err(QdrantClientError::from)?;
     }
 
     let mut payload_hashmap = HashMap::new();
-    payload_hashmap.insert("table_name", serde_json::Value::from(table_name));
     payload_hashmap.insert(
-        "model",
-        serde_json::Value::from(instances::MODEL_NAME.to_string()),
+        "model".to_string(),
+        serde_json::Value::from(embedding_config.model.clone()),
     );
-    payload_hashmap.insert("document_id", serde_json::Value::from(id));
-    if let Some(user_id) = user_id {
-        payload_hashmap.insert("user_id", serde_json::Value::from(user_id));
-    }
+    payload_hashmap.insert("dimension".to_string(), serde_json::Value::from(embedding_size));
 
     // Insert the data into the vector DB
-    let points = chunks_to_points(chunk_embeddings.clone(), payload_hashmap.clone());
-    let upsert_result = client
-        .upsert_points(
-            UpsertPoints {
-                collection_name: COLLECTION_NAME.to_string(),
-                points,
-                ..Default::default()
-            }
-        )
-        .await;
-
-    if let Err(e) = upsert_result {
-        if e.to_string().starts_with("status: NotFound") {
-            // Retry the upsert operation after creating the collection
-            let points = chunks_to_points(chunk_embeddings, payload_hashmap);
-            client
-                .upsert_points(
-                    UpsertPoints {
-                        collection_name: COLLECTION_NAME.to_string(),
-                        points,
-                        ..Default::default()
-                    }
-                )
-                .await
-                .map_err(QdrantClientError::from)?;
-        }
+    let points = chunks
+        .iter()
+        .zip(chunk_vectors)
+        .map(|(pending, vectors)| chunk_to_point(pending, vectors, &payload_hashmap))
+        .collect::<Vec<VectorPoint>>();
+    store.upsert_points(&collection.name, points).await?;
 
-        // For any other error, convert and return it
-        return Err(EmbeddingError::from(QdrantClientError::from(e)));
-    }
+    Ok(())
+}
 
-    let db = get_db_instance().await;
+// The live collection, as written by gRPC ingest and imports
+pub async fn live_target() -> Result<IngestTarget, EmbeddingError> {
+    Ok(IngestTarget {
+        collection: write_collection().await?,
+        mark_synced: true,
+        skip_unchanged: true,
+    })
+}
 
-    let now: DateTime<Utc> = Utc::now();
+// `read_at` is when the documents' text was read from their rows, recorded as their sync time
+pub async fn create_and_save_embeddings(
+    documents: Vec<VectorDbDocument>,
+    read_at: DateTime<Utc>,
+) -> Result<bool, EmbeddingError> {
+    create_and_save_embeddings_into(documents, read_at, &live_target().await?).await
+}
 
-    // Format the DateTime as a string for MySQL
-    let formatted_now = now.format("%Y-%m-%d %H:%M:%S").to_string();
+// The text a document is chunked from, its cleaned HTML if it was cleaned
+fn chunked_text<'a>(
+    document: &'a VectorDbDocument,
+    cleaned: &'a HashMap<DocumentKey, CleanedText>,
+) -> &'a str {
+    cleaned
+        .get(&document_key(document))
+        .map_or(&document.content, |cleaned| &cleaned.text)
+}
 
-    let sql = format!(
-        "UPDATE {} SET updated_at = ?, qdrant_sync_at = ? WHERE id = ?;",
-        table_name
-    );
-    db.execute(Statement::from_sql_and_values(
-        DatabaseBackend::MySql,
-        sql,
-        [
-            formatted_now.clone().into(),
-            formatted_now.into(),
-            id.into(),
-        ],
-    ))
-    .await?;
-    Ok(())
+// Duplicates of tables that skip them are only recorded, not embedded
+fn skips_duplicate(document: &VectorDbDocument, duplicates: &HashMap<DocumentKey, i64>) -> bool {
+    duplicates.contains_key(&document_key(document))
+        && embeddable_table(&document.table_name).and_then(|table| table.duplicates)
+            == Some(DuplicateHandling::Skip)
 }
 
-pub async fn create_and_save_embeddings(
+// Only registered tables can be embedded, and their sync state is written back by name
+fn check_tables(documents: &[VectorDbDocument]) -> Result<(), EmbeddingError> {
+    match documents
+        .iter()
+        .find(|document| embeddable_table(&document.table_name).is_none())
+    {
+        Some(document) => Err(EmbeddingError::UnknownTable(document.table_name.clone())),
+        None => Ok(()),
+    }
+}
+
+pub async fn create_and_save_embeddings_into(
     documents: Vec<VectorDbDocument>,
+    read_at: DateTime<Utc>,
+    target: &IngestTarget,
 ) -> Result<bool, EmbeddingError> {
-    let max_length = 8192;
+    check_tables(&documents)?;
+    let ExtractedDocuments { documents, failed } = extract_file_texts(documents).await?;
+    // The rest of the batch is still embedded
+    for (document, err) in &failed {
+        log::warn!(
+            "Couldn't extract the text of {} {}: {}",
+            document.table_name,
+            document.id,
+            err
+        );
+        if target.mark_synced {
+            if let Err(record_err) = record_failure(std::slice::from_ref(document), err).await {
+                log::warn!("Couldn't record the failed sync: {}", record_err);
+            }
+        }
+    }
 
     let max_document_batch_size = std::env::var("MAX_DOCUMENT_BATCH_SIZE")
         .ok()
         .and_then(|s| s.parse::<usize>().ok())
         .unwrap_or(MAX_DOCUMENT_BATCH_SIZE);
 
+    let max_pending_chunk_text = std::env::var("MAX_PENDING_CHUNK_TEXT")
+        .ok()
+        .and_then(|s| s.parse::<usize>().ok())
+        .unwrap_or(MAX_PENDING_CHUNK_TEXT);
+
     let available_parallelism = std::thread::available_parallelism()
         .map(|n| n.get())
         .unwrap_or(max_document_batch_size);
     let documents_chunk_size = (documents.len() / available_parallelism).max(1);
 
     let documents = Arc::new(Mutex::new(documents));
-    let qdrant_client = get_qdrant_instance().await;
-    let collection_exists = Arc::new(AtomicBool::new(
-        qdrant_client
-            .collection_exists(COLLECTION_NAME)
-            .await
-            .map_err(QdrantClientError::from)?
-    ));
+    let store = get_vector_store_instance().await;
+    let collection_existed = store.collection_exists(&target.collection.name).await?;
+    let mut collection_exists = collection_existed;
 
     while {
         let docs = documents.lock().await;
         !docs.is_empty()
     } {
-        let documents_chunk = {
+        let mut documents_chunk = {
             let mut docs = documents.lock().await;
             let docs_len = docs.len();
             docs.drain(..std::cmp::min(documents_chunk_size, docs_len))
                 .collect::<Vec<_>>()
         };
 
-        // Check if collection exists
-        if collection_exists.load(Ordering::SeqCst) {
-            let document_ids: Vec<i64> = documents_chunk.iter().map(|doc| doc.id).collect();
-            qdrant_client
-                .delete_points(
-                    DeletePoints {
-                        collection_name: COLLECTION_NAME.to_string(),
-                        points: Some(PointsSelector {
-                            points_selector_one_of: Some(PointsSelectorOneOf::Filter(Filter::must([
-                                Condition::matches("document_id", document_ids),
-                            ]))),
-                        }),
-                        ..Default::default()
-                    }
-                )
-                .await
-                .map_err(QdrantClientError::from)?;
+        let frames = load_frames(&documents_chunk).await?;
+        let cleaned = clean_documents(&documents_chunk);
+        let hashes: HashMap<DocumentKey, String> = documents_chunk
+            .iter()
+            .map(|document| {
+                let key = document_key(document);
+                let hash = content_hash(chunked_text(document, &cleaned), frames.get(&key));
+                (key, hash)
+            })
+            .collect();
+
+        // Documents embedded from the same text before don't need it again
+        if target.skip_unchanged {
+            let unchanged = skip_unchanged(&hashes, read_at).await?;
+            documents_chunk.retain(|document| !unchanged.contains(&document_key(document)));
         }
 
-        // Iterate over each document in the chunk of documents
-        let chunk_tasks: Vec<_> = documents_chunk
-            .into_iter()
-            .filter(|document| !document.content.is_empty())
-            .map(|document| {
-                let collection_exists = Arc::clone(&collection_exists);
-                async move {
-                    let mut chunk_iterator =
-                        StringChunkIterator::new(&document.content, max_length);
-                    let mut chunks: Vec<(&str, usize, usize)> = Vec::new();
-                    let mut combined_length = 0;
-
-                    while let Some(chunk) = chunk_iterator.next().await {
-                        let chunk = chunk?;
-
-                        let max_chunk_text_length = std::env::var("MAX_CHUNK_TEXT_LENGTH")
-                            .ok()
-                            .and_then(|s| s.parse::<usize>().ok())
-                            .unwrap_or(MAX_CHUNK_TEXT_LENGTH);
-
-                        if combined_length + chunk.0.len() > max_chunk_text_length && !chunks.is_empty() {
-                            process_chunks(
-                                document.id,
-                                &document.table_name,
-                                document.user_id,
-                                chunks.clone(),
-                            ).await?;
-                            chunks.clear();
-                            combined_length = 0;
-                            collection_exists.store(true, Ordering::SeqCst);
-                        }
-                        chunks.push(chunk);
-                        combined_length += chunk.0.len();
+        let minhashes: HashMap<DocumentKey, MinHash> = documents_chunk
+            .iter()
+            .filter(|document| {
+                embeddable_table(&document.table_name).is_some_and(|table| table.duplicates.is_some())
+            })
+            .filter_map(|document| {
+                MinHash::of(chunked_text(document, &cleaned))
+                    .map(|minhash| (document_key(document), minhash))
+            })
+            .collect();
+        let duplicates = find_duplicates(&documents_chunk, &minhashes).await?;
+
+        let chunk_counts = match embed_documents(
+            &documents_chunk,
+            &frames,
+            &cleaned,
+            &duplicates,
+            target,
+            collection_exists,
+            max_pending_chunk_text,
+        )
+        .await
+        {
+            Ok(chunk_counts) => chunk_counts,
+            Err(err) => {
+                if target.mark_synced {
+                    if let Err(record_err) = record_failure(&documents_chunk, &err).await {
+                        log::warn!("Couldn't record the failed sync: {}", record_err);
                     }
-
-                    if !chunks.is_empty() {
-                        process_chunks(
-                            document.id,
-                            &document.table_name,
-                            document.user_id,
-                            chunks,
-                        ).await?;
-                        collection_exists.store(true, Ordering::SeqCst);
+                }
+                return Err(err);
+            }
+        };
+        collection_exists |= chunk_counts.values().any(|count| *count > 0);
+
+        if target.mark_synced {
+            let synced = documents_chunk
+                .iter()
+                .map(|document| {
+                    let key = document_key(document);
+                    SyncedDocument {
+                        document,
+                        chunk_count: chunk_counts.get(&key).copied().unwrap_or_default(),
+                        content_hash: hashes[&key].clone(),
+                        html_cleaned: cleaned.contains_key(&key),
+                        minhash: minhashes.get(&key).map(MinHash::to_bytes),
+                        duplicate_of: duplicates.get(&key).copied(),
+                        read_at,
                     }
-                    Ok::<(), EmbeddingError>(())
+                })
+                .collect();
+            mark_synced(synced).await?;
+            save_bands(&documents_chunk, &minhashes, &duplicates).await?;
+        }
+    }
+
+    // A brand new live collection is the first chance to point the alias at it
+    if !collection_existed && target.mark_synced {
+        ensure_alias().await?;
+    }
+
+    // Documents whose file couldn't be extracted weren't embedded
+    Ok(failed.is_empty())
+}
+
+// Replaces the stored chunks of the documents, returning how many each has now
+async fn embed_documents(
+    documents: &[VectorDbDocument],
+    frames: &HashMap<DocumentKey, DocumentFrame>,
+    cleaned: &HashMap<DocumentKey, CleanedText>,
+    duplicates: &HashMap<DocumentKey, i64>,
+    target: &IngestTarget,
+    collection_exists: bool,
+    max_pending_chunk_text: usize,
+) -> Result<HashMap<DocumentKey, u32>, EmbeddingError> {
+    let mut chunk_counts = HashMap::new();
+    if documents.is_empty() {
+        return Ok(chunk_counts);
+    }
+
+    if collection_exists {
+        let store = get_vector_store_instance().await;
+        let keys: Vec<DocumentKey> = documents.iter().map(document_key).collect();
+        store.delete_documents(&target.collection.name, &keys).await?;
+    }
+
+    let documents: Vec<(&VectorDbDocument, TextStructure)> = documents
+        .iter()
+        .filter(|document| !chunked_text(document, cleaned).is_empty())
+        .filter(|document| !skips_duplicate(document, duplicates))
+        .map(|document| (document, TextStructure::of(chunked_text(document, cleaned))))
+        .collect();
+
+    // Chunks from consecutive documents share embedding batches
+    let mut pending: Vec<PendingChunk> = Vec::new();
+    let mut pending_length = 0;
+
+    for (document, structure) in &documents {
+        let document = *document;
+        let key = document_key(document);
+        let frame = frames.get(&key);
+        let text = chunked_text(document, cleaned);
+        let document_language = detect_language(text);
+        let mut chunk_iterator = StringChunkIterator::new(text, chunk_token_budget(frame))
+            .with_language(document_language);
+
+        while let Some(chunk) = chunk_iterator.next().await {
+            let chunk = chunk?;
+            if chunk.text.is_empty() {
+                continue;
+            }
+
+            if pending_length + chunk.text.len() > max_pending_chunk_text && !pending.is_empty() {
+                process_chunks(&pending, target).await?;
+                pending.clear();
+                pending_length = 0;
+            }
+            pending_length += chunk.text.len();
+            let index = chunk_counts.entry(key.clone()).or_insert(0);
+            pending.push(PendingChunk {
+                document,
+                frame,
+                cleaned: cleaned.get(&key),
+                structure,
+                index: *index,
+                language: chunk_language(chunk.text, document_language),
+                duplicate_of: duplicates.get(&key).copied(),
+                chunk,
+            });
+            *index += 1;
+        }
+    }
+
+    if !pending.is_empty() {
+        process_chunks(&pending, target).await?;
+    }
+
+    Ok(chunk_counts)
+}
+
+// Embeds a single document whose text is read `window_chars` characters at a time, for
+// texts too large to hold in memory whole. `read_window` gets the character position
+// to read from and returns fewer characters, or none, at the end of the text. HTML
+// can't be cleaned a window at a time, so it's embedded as it is.
+pub async fn create_and_save_streamed_embeddings<F, Fut>(
+    document: VectorDbDocument,
+    window_chars: usize,
+    mut read_window: F,
+    read_at: DateTime<Utc>,
+    target: &IngestTarget,
+) -> Result<(), EmbeddingError>
+where
+    F: FnMut(usize) -> Fut,
+    Fut: Future<Output = Result<Option<String>, EmbeddingError>>,
+{
+    let documents = std::slice::from_ref(&document);
+    check_tables(documents)?;
+    let frames = load_frames(documents).await?;
+    let frame = frames.get(&document_key(&document));
+
+    let result =
+        save_streamed_document(&document, frame, window_chars, &mut read_window, read_at, target)
+            .await;
+    if let Err(err) = &result {
+        if target.mark_synced {
+            if let Err(record_err) = record_failure(documents, err).await {
+                log::warn!("Couldn't record the failed sync: {}", record_err);
+            }
+        }
+    }
+
+    result
+}
+
+// The text is read twice: once to tell from its hash and MinHash whether it needs
+// embedding at all, then again to embed it
+async fn save_streamed_document<F, Fut>(
+    document: &VectorDbDocument,
+    frame: Option<&DocumentFrame>,
+    window_chars: usize,
+    read_window: &mut F,
+    read_at: DateTime<Utc>,
+    target: &IngestTarget,
+) -> Result<(), EmbeddingError>
+where
+    F: FnMut(usize) -> Fut,
+    Fut: Future<Output = Result<Option<String>, EmbeddingError>>,
+{
+    let documents = std::slice::from_ref(document);
+    let key = document_key(document);
+    let finds_duplicates =
+        embeddable_table(&document.table_name).is_some_and(|table| table.duplicates.is_some());
+    let scanned = scan_streamed_text(frame, window_chars, read_window, finds_duplicates).await?;
+
+    if target.skip_unchanged {
+        let hashes = HashMap::from([(key.clone(), scanned.content_hash.clone())]);
+        if skip_unchanged(&hashes, read_at).await?.contains(&key) {
+            return Ok(());
+        }
+    }
+
+    let minhashes: HashMap<DocumentKey, MinHash> = scanned
+        .minhash
+        .map(|minhash| (key.clone(), minhash))
+        .into_iter()
+        .collect();
+    let duplicates = find_duplicates(documents, &minhashes).await?;
+
+    let store = get_vector_store_instance().await;
+    let collection_existed = store.collection_exists(&target.collection.name).await?;
+    // Deleted once up front; every window after that only adds chunks
+    if collection_existed {
+        store
+            .delete_documents(&target.collection.name, std::slice::from_ref(&key))
+            .await?;
+    }
+
+    let chunk_count = if skips_duplicate(document, &duplicates) {
+        0
+    } else {
+        let duplicate_of = duplicates.get(&key).copied();
+        embed_streamed_document(
+            document,
+            frame,
+            window_chars,
+            read_window,
+            duplicate_of,
+            target,
+        )
+        .await?
+    };
+
+    if !target.mark_synced {
+        return Ok(());
+    }
+    mark_synced(vec![SyncedDocument {
+        document,
+        content_hash: scanned.content_hash,
+        chunk_count,
+        html_cleaned: false,
+        minhash: minhashes.get(&key).map(MinHash::to_bytes),
+        duplicate_of: duplicates.get(&key).copied(),
+        read_at,
+    }])
+    .await?;
+    save_bands(documents, &minhashes, &duplicates).await?;
+
+    if !collection_existed && chunk_count > 0 {
+        ensure_alias().await?;
+    }
+
+    Ok(())
+}
+
+// What a streamed document's sync state and duplicate check are made from
+struct ScannedText {
+    content_hash: String,
+    minhash: Option<MinHash>,
+}
+
+// Reads the whole text a window at a time without keeping it
+async fn scan_streamed_text<F, Fut>(
+    frame: Option<&DocumentFrame>,
+    window_chars: usize,
+    read_window: &mut F,
+    with_minhash: bool,
+) -> Result<ScannedText, EmbeddingError>
+where
+    F: FnMut(usize) -> Fut,
+    Fut: Future<Output = Result<Option<String>, EmbeddingError>>,
+{
+    let mut hasher = ContentHasher::new(frame);
+    let mut minhasher = with_minhash.then(MinHasher::default);
+    let mut read_chars = 0;
+
+    loop {
+        let window = read_window(read_chars).await?.unwrap_or_default();
+        let window_length = window.chars().count();
+        read_chars += window_length;
+        hasher.update(&window);
+        if let Some(minhasher) = &mut minhasher {
+            minhasher.update(&window);
+        }
+        if window_length < window_chars {
+            break;
+        }
+    }
+
+    Ok(ScannedText {
+        content_hash: hasher.finish(),
+        minhash: minhasher.and_then(MinHasher::finish),
+    })
+}
+
+// Chunks the text as it's read, as if it were read whole: a chunk that runs up to the end
+// of what's been read so far may have been cut short, so it's chunked again with the next
+// window. Offsets are into the full text.
+async fn embed_streamed_document<F, Fut>(
+    document: &VectorDbDocument,
+    frame: Option<&DocumentFrame>,
+    window_chars: usize,
+    read_window: &mut F,
+    duplicate_of: Option<i64>,
+    target: &IngestTarget,
+) -> Result<u32, EmbeddingError>
+where
+    F: FnMut(usize) -> Fut,
+    Fut: Future<Output = Result<Option<String>, EmbeddingError>>,
+{
+    let mut structure = TextStructure::default();
+    // Told from the first window
+    let mut document_language = None;
+    let mut buffer = String::new();
+    // Byte offset of the buffer in the full text
+    let mut offset = 0;
+    let mut read_chars = 0;
+    let mut chunk_count = 0;
+    let mut finished = false;
+
+    while !finished {
+        let window = read_window(read_chars).await?.unwrap_or_default();
+        let window_length = window.chars().count();
+        read_chars += window_length;
+        finished = window_length < window_chars;
+        structure.feed(&window);
+        if read_chars == window_length {
+            document_language = detect_language(&window);
+        }
+        if finished {
+            structure.finish();
+        }
+        buffer.push_str(&window);
+
+        let consumed = {
+            let mut pending: Vec<PendingChunk> = Vec::new();
+            let mut consumed = 0;
+            let mut chunk_iterator = StringChunkIterator::new(&buffer, chunk_token_budget(frame))
+                .with_language(document_language);
+
+            while let Some(chunk) = chunk_iterator.next().await {
+                let mut chunk = chunk?;
+                if !finished && chunk.end == buffer.len() {
+                    break;
                 }
-            })
+                consumed = chunk.end;
+                if chunk.text.is_empty() {
+                    continue;
+                }
+
+                chunk.start += offset;
+                chunk.end += offset;
+                pending.push(PendingChunk {
+                    document,
+                    frame,
+                    cleaned: None,
+                    structure: &structure,
+                    index: chunk_count + pending.len() as u32,
+                    language: chunk_language(chunk.text, document_language),
+                    duplicate_of,
+                    chunk,
+                });
+            }
+
+            if !pending.is_empty() {
+                process_chunks(&pending, target).await?;
+                chunk_count += pending.len() as u32;
+            }
+            consumed
+        };
+
+        buffer.drain(..consumed);
+        offset += consumed;
+    }
+
+    Ok(chunk_count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pending<'a>(
+        document: &'a VectorDbDocument,
+        structure: &'a TextStructure,
+        token_count: usize,
+    ) -> PendingChunk<'a> {
+        PendingChunk {
+            document,
+            frame: None,
+            cleaned: None,
+            structure,
+            index: 0,
+            language: None,
+            duplicate_of: None,
+            chunk: TextChunk {
+                text: "",
+                start: 0,
+                end: 0,
+                token_count,
+            },
+        }
+    }
+
+    fn packed_token_counts(token_counts: &[usize], max_tokens: usize, max_chunks: usize) -> Vec<Vec<usize>> {
+        let document = VectorDbDocument::default();
+        let structure = TextStructure::default();
+        let chunks: Vec<PendingChunk> = token_counts
+            .iter()
+            .map(|token_count| pending(&document, &structure, *token_count))
             .collect();
 
-        for task in chunk_tasks {
-            task.await?;
+        pack_batches(&chunks, max_tokens, max_chunks)
+            .into_iter()
+            .map(|batch| batch.iter().map(PendingChunk::token_count).collect())
+            .collect()
+    }
+
+    // Reads `text` the way the streamed import does, `window_chars` characters at a time
+    async fn scan(text: &str, window_chars: usize, with_minhash: bool) -> ScannedText {
+        let chars: Vec<char> = text.chars().collect();
+        let mut read_window = |position: usize| {
+            let window: String = chars.iter().skip(position).take(window_chars).collect();
+            async move { Ok(Some(window)) }
+        };
+
+        scan_streamed_text(None, window_chars, &mut read_window, with_minhash)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn scanned_text_matches_the_text_read_whole() {
+        // Long enough to be fingerprinted
+        let text = (0..60)
+            .map(|i| format!("Übersetzt heißt Rust {} Rost.", i))
+            .collect::<Vec<_>>()
+            .join(" ");
+        assert!(MinHash::of(&text).is_some());
+
+        for window_chars in [1, 7, text.chars().count(), 10_000] {
+            let scanned = scan(&text, window_chars, true).await;
+
+            assert_eq!(scanned.content_hash, content_hash(&text, None));
+            assert_eq!(scanned.minhash, MinHash::of(&text));
         }
     }
 
-    Ok(true)
-}
\ No newline at end of file
+    #[tokio::test]
+    async fn scanned_text_only_has_a_minhash_when_asked_for() {
+        let scanned = scan("Rust is a systems programming language.", 8, false).await;
+
+        assert_eq!(scanned.minhash, None);
+    }
+
+    #[test]
+    fn pack_batches_fills_batches_up_to_the_token_budget() {
+        assert_eq!(
+            packed_token_counts(&[3, 4, 2, 5, 1], 8, 64),
+            vec![vec![3, 4], vec![2, 5, 1]]
+        );
+    }
+
+    #[test]
+    fn pack_batches_limits_chunks_per_batch() {
+        assert_eq!(
+            packed_token_counts(&[1, 1, 1, 1, 1], 100, 2),
+            vec![vec![1, 1], vec![1, 1], vec![1]]
+        );
+    }
+
+    #[test]
+    fn pack_batches_gives_chunks_over_the_budget_a_batch_of_their_own() {
+        assert_eq!(
+            packed_token_counts(&[2, 20, 2], 8, 64),
+            vec![vec![2], vec![20], vec![2]]
+        );
+    }
+
+    #[test]
+    fn pack_batches_of_nothing_is_nothing() {
+        assert!(packed_token_counts(&[], 8, 64).is_empty());
+    }
+}
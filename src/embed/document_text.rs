@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+
+use sea_orm::{ConnectionTrait, DatabaseBackend, Statement, Value};
+
+use super::{
+    embedder::{count_tokens, render_template},
+    errors::EmbeddingError,
+    instances::get_db_instance,
+    tables::{embeddable_table, EmbeddableTable},
+};
+use crate::grpc::server::vecembed_rpc::VectorDbDocument;
+
+// A document by its table and id
+pub type DocumentKey = (String, i64);
+
+pub fn document_key(document: &VectorDbDocument) -> DocumentKey {
+    (document.table_name.clone(), document.id)
+}
+
+// The rendered template around the text column, embedded with every chunk of it.
+// Chunks keep their offsets into the text column itself.
+#[derive(Debug, Default)]
+pub struct DocumentFrame {
+    pub before: String,
+    pub after: String,
+    pub token_count: usize,
+}
+
+impl DocumentFrame {
+    pub fn wrap(&self, chunk: &str) -> String {
+        format!("{}{}{}", self.before, chunk, self.after)
+    }
+}
+
+// Names of the `{...}` placeholders in a template
+pub fn placeholders(template: &str) -> Vec<&str> {
+    let mut names = Vec::new();
+    let mut rest = template;
+
+    while let Some(open) = rest.find('{') {
+        rest = &rest[open + 1..];
+        match rest.find('}') {
+            Some(close) => {
+                names.push(&rest[..close]);
+                rest = &rest[close + 1..];
+            }
+            None => break,
+        }
+    }
+
+    names
+}
+
+// Columns of the row and its relations the template uses, other than the text column
+fn template_columns(table: &EmbeddableTable, template: &str) -> Vec<(String, String)> {
+    let mut columns: Vec<(String, String)> = Vec::new();
+    for name in placeholders(template) {
+        if name == table.text_column || columns.iter().any(|(placeholder, _)| placeholder == name) {
+            continue;
+        }
+        let column = match name.split_once('.') {
+            Some(_) => name.to_string(),
+            None => format!("{}.{}", table.name, name),
+        };
+        columns.push((name.to_string(), column));
+    }
+
+    columns
+}
+
+async fn load_table_frames(
+    table: &EmbeddableTable,
+    template: &str,
+    ids: &[i64],
+) -> Result<HashMap<i64, DocumentFrame>, EmbeddingError> {
+    let text_placeholder = format!("{{{}}}", table.text_column);
+    let (before, after) = template
+        .split_once(&text_placeholder)
+        .unwrap_or((template, ""));
+    let columns = template_columns(table, template);
+
+    let mut rows: HashMap<i64, Vec<(String, String)>> = ids.iter().map(|id| (*id, Vec::new())).collect();
+    if !columns.is_empty() {
+        let selects = columns
+            .iter()
+            .map(|(placeholder, column)| format!("CAST({} AS CHAR) AS `{}`", column, placeholder))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let joins = table
+            .relations
+            .iter()
+            .map(|relation| {
+                format!(
+                    " LEFT JOIN {rel} ON {rel}.{key} = {table}.{foreign_key}",
+                    rel = relation.table,
+                    key = relation.key,
+                    table = table.name,
+                    foreign_key = relation.foreign_key
+                )
+            })
+            .collect::<String>();
+        let id_list = vec!["?"; ids.len()].join(", ");
+
+        let db = get_db_instance().await;
+        let results = db
+            .query_all(Statement::from_sql_and_values(
+                DatabaseBackend::MySql,
+                format!(
+                    "SELECT CAST({table}.{id} AS SIGNED) AS `__id`, {selects} FROM {table}{joins}
+                    WHERE {table}.{id} IN ({id_list})",
+                    table = table.name,
+                    id = table.id_column,
+                ),
+                ids.iter().map(|id| Value::from(*id)).collect::<Vec<_>>(),
+            ))
+            .await?;
+
+        for row in results {
+            let id: i64 = row.try_get("", "__id")?;
+            let mut values = Vec::new();
+            for (placeholder, _) in &columns {
+                // Missing relations and NULL columns render as empty
+                let value: Option<String> = row.try_get("", placeholder)?;
+                values.push((placeholder.clone(), value.unwrap_or_default()));
+            }
+            rows.insert(id, values);
+        }
+    }
+
+    let mut frames = HashMap::new();
+    for (id, values) in rows {
+        let values = values
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect::<Vec<_>>();
+        let before = render_template(before, &values);
+        let after = render_template(after, &values);
+        let token_count = count_tokens(&[format!("{}{}", before, after)]).await;
+
+        frames.insert(id, DocumentFrame { before, after, token_count });
+    }
+
+    Ok(frames)
+}
+
+// Frames for the documents of tables with a text template, keyed by table and document id
+pub async fn load_frames(
+    documents: &[VectorDbDocument],
+) -> Result<HashMap<DocumentKey, DocumentFrame>, EmbeddingError> {
+    let mut ids_by_table: HashMap<&str, Vec<i64>> = HashMap::new();
+    for document in documents {
+        ids_by_table
+            .entry(document.table_name.as_str())
+            .or_default()
+            .push(document.id);
+    }
+
+    let mut frames = HashMap::new();
+    for (table_name, ids) in ids_by_table {
+        let table = embeddable_table(table_name)
+            .ok_or_else(|| EmbeddingError::UnknownTable(table_name.to_string()))?;
+        let Some(template) = table.text_template else {
+            continue;
+        };
+
+        for (id, frame) in load_table_frames(table, template, &ids).await? {
+            frames.insert((table_name.to_string(), id), frame);
+        }
+    }
+
+    Ok(frames)
+}
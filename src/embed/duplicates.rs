@@ -0,0 +1,383 @@
+use std::collections::{HashMap, VecDeque};
+
+use sea_orm::{
+    ActiveValue::{NotSet, Set},
+    ColumnTrait, Condition, EntityTrait, QueryFilter, QueryOrder, QuerySelect,
+};
+
+use super::{
+    document_text::{document_key, DocumentKey},
+    errors::EmbeddingError,
+    instances::get_db_instance,
+    tables::embeddable_table,
+};
+use crate::entities::{vecembed_minhash_bands, vecembed_sync_state};
+use crate::grpc::server::vecembed_rpc::VectorDbDocument;
+
+// Words per shingle; runs of words rather than single ones, so word order counts
+const SHINGLE_WORDS: usize = 3;
+// Shorter texts, like titles or one-line messages, match too easily to be compared
+const MIN_FINGERPRINT_WORDS: usize = 50;
+// Rows are compared when all hashes of one of their bands match, which is likely from a
+// similarity of about (1/16)^(1/8) = 0.71 up
+const MINHASH_PERMUTATIONS: usize = 128;
+const MINHASH_BANDS: usize = 16;
+const NEAR_DUPLICATE_SIMILARITY: f64 = 0.8;
+// Candidates compared per document, in case some boilerplate makes a band common
+const MAX_CANDIDATES: u64 = 1000;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+
+fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+// SplitMix64's finalizer, which turns one shingle hash into as many independent ones
+// as the signature needs
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+// MinHash signature of a text's word shingles. The share of entries two signatures
+// agree on estimates the share of shingles their texts have in common.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MinHash(Vec<u32>);
+
+impl MinHash {
+    // None for texts too short to compare
+    pub fn of(text: &str) -> Option<Self> {
+        let mut hasher = MinHasher::default();
+        hasher.update(text);
+        hasher.finish()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.0.iter().flat_map(|value| value.to_le_bytes()).collect()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != MINHASH_PERMUTATIONS * 4 {
+            return None;
+        }
+        Some(MinHash(
+            bytes
+                .chunks_exact(4)
+                .map(|value| u32::from_le_bytes([value[0], value[1], value[2], value[3]]))
+                .collect(),
+        ))
+    }
+
+    pub fn similarity(&self, other: &MinHash) -> f64 {
+        let matching = self.0.iter().zip(&other.0).filter(|(a, b)| a == b).count();
+        matching as f64 / MINHASH_PERMUTATIONS as f64
+    }
+
+    fn band_hashes(&self) -> impl Iterator<Item = (u8, u64)> + '_ {
+        self.0
+            .chunks(MINHASH_PERMUTATIONS / MINHASH_BANDS)
+            .enumerate()
+            .map(|(band, values)| {
+                let hash = values.iter().fold(FNV_OFFSET_BASIS, |hash, value| {
+                    fnv1a(hash, &value.to_le_bytes())
+                });
+                (band as u8, hash)
+            })
+    }
+}
+
+// Builds a MinHash from text read a piece at a time, as if it were read whole
+pub struct MinHasher {
+    seeds: Vec<u64>,
+    signature: Vec<u32>,
+    // The last words read, up to a shingle of them
+    recent: VecDeque<String>,
+    // A word the last piece may have ended in the middle of
+    partial: String,
+    words: usize,
+}
+
+impl Default for MinHasher {
+    fn default() -> Self {
+        MinHasher {
+            seeds: (1..=MINHASH_PERMUTATIONS as u64).map(mix).collect(),
+            signature: vec![u32::MAX; MINHASH_PERMUTATIONS],
+            recent: VecDeque::with_capacity(SHINGLE_WORDS),
+            partial: String::new(),
+            words: 0,
+        }
+    }
+}
+
+impl MinHasher {
+    fn add_word(&mut self, word: String) {
+        self.words += 1;
+        self.recent.push_back(word.to_lowercase());
+        if self.recent.len() < SHINGLE_WORDS {
+            return;
+        }
+
+        let hash = self.recent.iter().fold(FNV_OFFSET_BASIS, |hash, word| {
+            fnv1a(fnv1a(hash, word.as_bytes()), b" ")
+        });
+        for (minimum, seed) in self.signature.iter_mut().zip(&self.seeds) {
+            *minimum = (*minimum).min(mix(hash ^ seed) as u32);
+        }
+        self.recent.pop_front();
+    }
+
+    pub fn update(&mut self, text: &str) {
+        for c in text.chars() {
+            if c.is_alphanumeric() {
+                self.partial.push(c);
+            } else if !self.partial.is_empty() {
+                let word = std::mem::take(&mut self.partial);
+                self.add_word(word);
+            }
+        }
+    }
+
+    pub fn finish(mut self) -> Option<MinHash> {
+        if !self.partial.is_empty() {
+            let word = std::mem::take(&mut self.partial);
+            self.add_word(word);
+        }
+        if self.words < MIN_FINGERPRINT_WORDS {
+            return None;
+        }
+
+        Some(MinHash(self.signature))
+    }
+}
+
+fn near_duplicate_similarity() -> f64 {
+    std::env::var("NEAR_DUPLICATE_SIMILARITY")
+        .ok()
+        .and_then(|s| s.parse::<f64>().ok())
+        .filter(|similarity| *similarity > 0.0 && *similarity <= 1.0)
+        .unwrap_or(NEAR_DUPLICATE_SIMILARITY)
+}
+
+// The lowest stored row of the table that's similar enough and isn't a duplicate
+// itself, if any
+async fn stored_original(
+    document: &VectorDbDocument,
+    minhash: &MinHash,
+    similarity: f64,
+) -> Result<Option<i64>, EmbeddingError> {
+    use vecembed_minhash_bands::Column as BandColumn;
+    use vecembed_sync_state::Column as StateColumn;
+
+    let db = get_db_instance().await;
+    let bands = minhash
+        .band_hashes()
+        .fold(Condition::any(), |bands, (band, hash)| {
+            bands.add(
+                Condition::all()
+                    .add(BandColumn::Band.eq(band))
+                    .add(BandColumn::Hash.eq(hash)),
+            )
+        });
+
+    let candidate_ids: Vec<i64> = vecembed_minhash_bands::Entity::find()
+        .select_only()
+        .column(BandColumn::RowId)
+        .distinct()
+        .filter(BandColumn::TableName.eq(document.table_name.as_str()))
+        .filter(BandColumn::RowId.ne(document.id))
+        .filter(bands)
+        .limit(MAX_CANDIDATES)
+        .into_tuple()
+        .all(db)
+        .await?;
+    if candidate_ids.is_empty() {
+        return Ok(None);
+    }
+
+    let original = vecembed_sync_state::Entity::find()
+        .filter(StateColumn::TableName.eq(document.table_name.as_str()))
+        .filter(StateColumn::RowId.is_in(candidate_ids))
+        .filter(StateColumn::DuplicateOf.is_null())
+        .order_by_asc(StateColumn::RowId)
+        .all(db)
+        .await?
+        .into_iter()
+        .find(|state| {
+            state
+                .minhash
+                .as_deref()
+                .and_then(MinHash::from_bytes)
+                .is_some_and(|other| minhash.similarity(&other) >= similarity)
+        });
+
+    Ok(original.map(|state| state.row_id))
+}
+
+// Finds the documents that nearly duplicate a row embedded before them, or an earlier
+// document of the same batch, returning the id of the row each one duplicates
+pub async fn find_duplicates(
+    documents: &[VectorDbDocument],
+    minhashes: &HashMap<DocumentKey, MinHash>,
+) -> Result<HashMap<DocumentKey, i64>, EmbeddingError> {
+    let similarity = near_duplicate_similarity();
+    let mut duplicates = HashMap::new();
+    // Documents of this batch that aren't duplicates themselves
+    let mut originals: Vec<(&VectorDbDocument, &MinHash)> = Vec::new();
+
+    for document in documents {
+        let key = document_key(document);
+        let Some(minhash) = minhashes.get(&key) else {
+            continue;
+        };
+
+        let batch_original = originals
+            .iter()
+            .find(|(original, other)| {
+                original.table_name == document.table_name
+                    && minhash.similarity(other) >= similarity
+            })
+            .map(|(original, _)| original.id);
+        let original = match batch_original {
+            Some(id) => Some(id),
+            None => stored_original(document, minhash, similarity).await?,
+        };
+
+        match original {
+            Some(id) => {
+                duplicates.insert(key, id);
+            }
+            None => originals.push((document, minhash)),
+        }
+    }
+
+    Ok(duplicates)
+}
+
+// Replaces the band hashes later rows find the documents by. Duplicates get none, so
+// rows are only ever recorded as duplicates of an original.
+pub async fn save_bands(
+    documents: &[VectorDbDocument],
+    minhashes: &HashMap<DocumentKey, MinHash>,
+    duplicates: &HashMap<DocumentKey, i64>,
+) -> Result<(), EmbeddingError> {
+    use vecembed_minhash_bands::{ActiveModel, Column, Entity};
+
+    let mut ids_by_table: HashMap<&str, Vec<i64>> = HashMap::new();
+    for document in documents {
+        if embeddable_table(&document.table_name).is_some_and(|table| table.duplicates.is_some())
+        {
+            ids_by_table
+                .entry(document.table_name.as_str())
+                .or_default()
+                .push(document.id);
+        }
+    }
+    if ids_by_table.is_empty() {
+        return Ok(());
+    }
+
+    let db = get_db_instance().await;
+    for (table_name, ids) in ids_by_table {
+        Entity::delete_many()
+            .filter(Column::TableName.eq(table_name))
+            .filter(Column::RowId.is_in(ids))
+            .exec(db)
+            .await?;
+    }
+
+    let mut bands: Vec<ActiveModel> = Vec::new();
+    for document in documents {
+        let key = document_key(document);
+        let Some(minhash) = minhashes.get(&key) else {
+            continue;
+        };
+        if duplicates.contains_key(&key) {
+            continue;
+        }
+        bands.extend(minhash.band_hashes().map(|(band, hash)| ActiveModel {
+            id: NotSet,
+            table_name: Set(document.table_name.clone()),
+            row_id: Set(document.id),
+            band: Set(band),
+            hash: Set(hash),
+        }));
+    }
+    if !bands.is_empty() {
+        Entity::insert_many(bands).exec(db).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(count: usize, offset: usize) -> String {
+        (offset..offset + count)
+            .map(|i| format!("word{}", i))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    #[test]
+    fn short_texts_arent_fingerprinted() {
+        assert_eq!(MinHash::of(&words(MIN_FINGERPRINT_WORDS - 1, 0)), None);
+        assert!(MinHash::of(&words(MIN_FINGERPRINT_WORDS, 0)).is_some());
+    }
+
+    #[test]
+    fn similarity_estimates_shared_shingles() {
+        let text = words(200, 0);
+        let minhash = MinHash::of(&text).unwrap();
+        // Case and punctuation don't count
+        let same = MinHash::of(&format!("{}!", text.to_uppercase())).unwrap();
+        let nearly_same = MinHash::of(&format!("{} {}", text, words(5, 1000))).unwrap();
+        let different = MinHash::of(&words(200, 5000)).unwrap();
+
+        assert_eq!(minhash.similarity(&same), 1.0);
+        assert!(minhash.similarity(&nearly_same) >= NEAR_DUPLICATE_SIMILARITY);
+        assert!(minhash.similarity(&different) < 0.1);
+    }
+
+    #[test]
+    fn band_hashes_of_near_duplicates_collide() {
+        let minhash = MinHash::of(&words(200, 0)).unwrap();
+        let nearly_same = MinHash::of(&format!("{} {}", words(200, 0), words(5, 1000))).unwrap();
+        let different = MinHash::of(&words(200, 5000)).unwrap();
+        let shared_bands = |other: &MinHash| {
+            minhash
+                .band_hashes()
+                .zip(other.band_hashes())
+                .filter(|(a, b)| a == b)
+                .count()
+        };
+
+        assert_eq!(minhash.band_hashes().count(), MINHASH_BANDS);
+        assert!(shared_bands(&nearly_same) > 0);
+        assert_eq!(shared_bands(&different), 0);
+    }
+
+    #[test]
+    fn minhash_survives_a_round_trip_through_bytes() {
+        let minhash = MinHash::of(&words(100, 0)).unwrap();
+
+        assert_eq!(MinHash::from_bytes(&minhash.to_bytes()), Some(minhash));
+        assert_eq!(MinHash::from_bytes(&[0; 16]), None);
+    }
+
+    #[test]
+    fn minhasher_matches_reading_the_text_whole() {
+        let text = words(300, 0);
+        let mut hasher = MinHasher::default();
+        // Windows end in the middle of words and of shingles
+        for window in text.as_bytes().chunks(7) {
+            hasher.update(std::str::from_utf8(window).unwrap());
+        }
+
+        assert_eq!(hasher.finish(), MinHash::of(&text));
+    }
+}
@@ -0,0 +1,283 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use async_openai::types::{CreateEmbeddingRequestArgs, Embedding};
+use futures::future::{BoxFuture, FutureExt};
+
+use super::{
+    errors::EmbeddingError,
+    instances::{get_provider_client_instance, get_tokenizer_instance},
+    limiter::{get_embedding_limiter, Priority},
+    retry::{embedding_breaker, is_batch_too_large, with_retry},
+};
+use crate::config::{get_config, EmbeddingProvider};
+
+const MAX_BATCH_TOKENS: usize = 32768;
+
+// Lowered whenever the server rejects a batch for its size, so later batches fit
+static LEARNED_BATCH_TOKENS: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+// Most tokens an ingest request should carry
+pub fn batch_token_limit() -> usize {
+    std::env::var("MAX_BATCH_TOKENS")
+        .ok()
+        .and_then(|s| s.parse::<usize>().ok())
+        .unwrap_or(MAX_BATCH_TOKENS)
+        .min(LEARNED_BATCH_TOKENS.load(Ordering::SeqCst))
+}
+
+// Fills `{name}` placeholders in a single pass, so values can't inject placeholders of their own
+pub fn render_template(template: &str, values: &[(&str, &str)]) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(open) = rest.find('{') {
+        rendered.push_str(&rest[..open]);
+        rest = &rest[open + 1..];
+
+        let placeholder = values.iter().find(|(name, _)| {
+            rest.strip_prefix(name)
+                .is_some_and(|after| after.starts_with('}'))
+        });
+        match placeholder {
+            Some((name, value)) => {
+                rendered.push_str(value);
+                rest = &rest[name.len() + 1..];
+            }
+            None => rendered.push('{'),
+        }
+    }
+    rendered.push_str(rest);
+
+    rendered
+}
+
+pub fn format_query(provider: &EmbeddingProvider, task_description: &str, query: &str) -> String {
+    let task_description = if task_description.is_empty() {
+        &get_config().embedding.task_description
+    } else {
+        task_description
+    };
+
+    render_template(
+        &provider.query_template,
+        &[("task", task_description), ("query", query)],
+    )
+}
+
+pub fn format_document(provider: &EmbeddingProvider, text: &str) -> String {
+    render_template(&provider.document_template, &[("text", text)])
+}
+
+// Matryoshka truncation: keep the leading dimensions, then rescale back to unit length
+pub fn truncate_embedding(mut embedding: Vec<f32>, dimension: Option<usize>) -> Vec<f32> {
+    let Some(dimension) = dimension else {
+        return embedding;
+    };
+    if dimension >= embedding.len() {
+        return embedding;
+    }
+
+    embedding.truncate(dimension);
+    let norm = embedding
+        .iter()
+        .map(|value| value * value)
+        .sum::<f32>()
+        .sqrt();
+    if norm > 0.0 {
+        embedding.iter_mut().for_each(|value| *value /= norm);
+    }
+
+    embedding
+}
+
+// Counted with the primary model's tokenizer, which is close enough to budget other providers
+pub async fn count_tokens(inputs: &[String]) -> usize {
+    let tokenizer = get_tokenizer_instance().await;
+
+    inputs
+        .iter()
+        .map(|input| {
+            tokenizer
+                .encode(input.as_str(), true)
+                .map(|encoding| encoding.len())
+                .unwrap_or(input.len() / 4)
+        })
+        .sum()
+}
+
+// Embeds a batch of texts with one provider's model, in input order
+pub async fn embed_texts_with(
+    provider: &EmbeddingProvider,
+    inputs: Vec<String>,
+    priority: Priority,
+) -> Result<Vec<Vec<f32>>, EmbeddingError> {
+    let tokens = count_tokens(&inputs).await;
+
+    embed_counted_texts(provider, inputs, tokens, priority).await
+}
+
+// For callers that already know how many tokens the inputs are
+pub async fn embed_counted_texts(
+    provider: &EmbeddingProvider,
+    inputs: Vec<String>,
+    tokens: usize,
+    priority: Priority,
+) -> Result<Vec<Vec<f32>>, EmbeddingError> {
+    let embedding_client = get_provider_client_instance(provider).await;
+
+    let response = with_retry(embedding_breaker(&provider.name), || async {
+        // Every attempt takes its own slot, so backoff sleeps don't hold one
+        let _permit = get_embedding_limiter().acquire(priority, tokens).await;
+
+        let request = CreateEmbeddingRequestArgs::default()
+            .model(&provider.model)
+            .input(inputs.clone())
+            .build()?;
+
+        Ok(embedding_client.embeddings().create(request).await?)
+    })
+    .await?;
+
+    embeddings_in_order(response.data, inputs.len(), provider.dimension)
+}
+
+// Without exactly one embedding per input there's no telling which text each belongs to
+fn embeddings_in_order(
+    mut data: Vec<Embedding>,
+    expected: usize,
+    dimension: Option<usize>,
+) -> Result<Vec<Vec<f32>>, EmbeddingError> {
+    if data.len() != expected {
+        return Err(EmbeddingError::EmbeddingCountMismatch {
+            expected,
+            actual: data.len(),
+        });
+    }
+    data.sort_by_key(|data| data.index);
+
+    Ok(data
+        .into_iter()
+        .map(|data| truncate_embedding(data.embedding, dimension))
+        .collect())
+}
+
+// The server's batch limits aren't known up front, so a batch it rejects for its size
+// is halved until the pieces fit
+pub fn embed_batch<'a>(
+    provider: &'a EmbeddingProvider,
+    texts: Vec<String>,
+    token_counts: Vec<usize>,
+) -> BoxFuture<'a, Result<Vec<Vec<f32>>, EmbeddingError>> {
+    async move {
+        let tokens = token_counts.iter().sum::<usize>();
+
+        match embed_counted_texts(provider, texts.clone(), tokens, Priority::Ingest).await {
+            Err(err) if texts.len() > 1 && is_batch_too_large(&err) => {
+                log::warn!(
+                    "Embedding batch of {} chunks ({} tokens) is too large for the server, splitting it",
+                    texts.len(),
+                    tokens
+                );
+                LEARNED_BATCH_TOKENS.fetch_min((tokens / 2).max(1), Ordering::SeqCst);
+
+                let middle = texts.len() / 2;
+                let mut texts = texts;
+                let mut token_counts = token_counts;
+                let second_texts = texts.split_off(middle);
+                let second_token_counts = token_counts.split_off(middle);
+
+                let mut embeddings = embed_batch(provider, texts, token_counts).await?;
+                embeddings.extend(embed_batch(provider, second_texts, second_token_counts).await?);
+
+                Ok(embeddings)
+            }
+            result => result,
+        }
+    }
+    .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn embedding(index: u32, value: f32) -> Embedding {
+        Embedding {
+            index,
+            object: "embedding".to_string(),
+            embedding: vec![value],
+        }
+    }
+
+    #[test]
+    fn embeddings_in_order_sorts_by_index() {
+        let data = vec![embedding(1, 1.0), embedding(2, 2.0), embedding(0, 0.0)];
+
+        let embeddings = embeddings_in_order(data, 3, None).unwrap();
+
+        assert_eq!(embeddings, vec![vec![0.0], vec![1.0], vec![2.0]]);
+    }
+
+    #[test]
+    fn embeddings_in_order_rejects_a_count_mismatch() {
+        let data = vec![embedding(0, 0.0), embedding(1, 1.0)];
+
+        let err = embeddings_in_order(data, 3, None).unwrap_err();
+
+        assert!(matches!(
+            err,
+            EmbeddingError::EmbeddingCountMismatch {
+                expected: 3,
+                actual: 2
+            }
+        ));
+    }
+
+    #[test]
+    fn truncate_embedding_keeps_leading_dimensions_at_unit_length() {
+        let truncated = truncate_embedding(vec![3.0, 4.0, 12.0], Some(2));
+
+        assert_eq!(truncated, vec![0.6, 0.8]);
+    }
+
+    #[test]
+    fn truncate_embedding_leaves_short_enough_embeddings_alone() {
+        let embedding = vec![0.5, 0.5, 0.5];
+
+        assert_eq!(truncate_embedding(embedding.clone(), None), embedding);
+        assert_eq!(truncate_embedding(embedding.clone(), Some(3)), embedding);
+        assert_eq!(truncate_embedding(embedding.clone(), Some(8)), embedding);
+    }
+
+    #[test]
+    fn truncate_embedding_leaves_zero_vectors_alone() {
+        assert_eq!(truncate_embedding(vec![0.0, 0.0, 1.0], Some(2)), vec![0.0, 0.0]);
+    }
+
+    #[test]
+    fn render_template_fills_placeholders() {
+        let rendered = render_template(
+            "Instruct: {task}\nQuery: {query}",
+            &[("task", "Find sources"), ("query", "rust")],
+        );
+
+        assert_eq!(rendered, "Instruct: Find sources\nQuery: rust");
+    }
+
+    #[test]
+    fn render_template_keeps_unknown_placeholders_and_stray_braces() {
+        let rendered = render_template("{text} {other} {", &[("text", "a")]);
+
+        assert_eq!(rendered, "a {other} {");
+    }
+
+    #[test]
+    fn render_template_doesnt_fill_placeholders_in_values() {
+        let rendered = render_template(
+            "{query} / {task}",
+            &[("query", "{task}"), ("task", "summarize")],
+        );
+
+        assert_eq!(rendered, "{task} / summarize");
+    }
+}
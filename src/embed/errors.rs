@@ -1,11 +1,19 @@
 use async_openai::error::OpenAIError;
 use thiserror::Error;
 use qdrant_client::QdrantError;
+use tonic::Code;
 
 #[derive(Error, Debug)]
 pub enum QdrantClientError {
     #[error("Qdrant client error: {0}")]
     ClientError(String),
+
+    #[error("Qdrant unavailable: {0}")]
+    Unavailable(String),
+
+    // Qdrant answered, refusing the request
+    #[error("Qdrant rejected the request: {0}")]
+    Rejected(String),
 }
 
 impl From<anyhow::Error> for QdrantClientError {
@@ -16,7 +24,21 @@ impl From<anyhow::Error> for QdrantClientError {
 
 impl From<QdrantError> for QdrantClientError {
     fn from(err: QdrantError) -> Self {
-        QdrantClientError::ClientError(err.to_string())
+        match &err {
+            QdrantError::ResponseError { status }
+                if matches!(
+                    status.code(),
+                    Code::Unavailable | Code::DeadlineExceeded | Code::Aborted | Code::ResourceExhausted
+                ) =>
+            {
+                QdrantClientError::Unavailable(err.to_string())
+            }
+            QdrantError::ResourceExhaustedError { .. } => {
+                QdrantClientError::Unavailable(err.to_string())
+            }
+            QdrantError::ResponseError { .. } => QdrantClientError::Rejected(err.to_string()),
+            _ => QdrantClientError::ClientError(err.to_string()),
+        }
     }
 }
 
@@ -36,4 +58,32 @@ pub enum EmbeddingError {
 
     #[error(transparent)]
     TaskJoinError(#[from] tokio::task::JoinError),
+
+    #[error("Collection `{collection}` stores {expected}-dimensional vectors, got {actual}")]
+    DimensionMismatch {
+        collection: String,
+        expected: u64,
+        actual: u64,
+    },
+
+    #[error("Collection `{collection}` has no `{vector}` vector")]
+    MissingVector { collection: String, vector: String },
+
+    #[error("No embedding provider is configured for vector `{0}`")]
+    UnknownVector(String),
+
+    #[error("`{0}` isn't an embeddable table")]
+    UnknownTable(String),
+
+    #[error("Backend unavailable: {0}")]
+    BackendUnavailable(String),
+
+    #[error("Couldn't extract text: {0}")]
+    Extraction(String),
+
+    #[error("Unsupported collection setting: {0}")]
+    UnsupportedSetting(String),
+
+    #[error("Embedding server returned {actual} embeddings for {expected} texts")]
+    EmbeddingCountMismatch { expected: usize, actual: usize },
 }
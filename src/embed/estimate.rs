@@ -0,0 +1,158 @@
+use std::fmt;
+use std::future::Future;
+
+use futures::stream::StreamExt;
+
+use super::{
+    chunk_strings::StringChunkIterator,
+    create::chunk_token_budget,
+    document_text::{document_key, load_frames},
+    errors::EmbeddingError,
+    extract::{clean_documents, extract_file_texts},
+    language::detect_language,
+};
+use crate::config::get_config;
+use crate::grpc::server::vecembed_rpc::VectorDbDocument;
+
+// Assumed embedding throughput when MAX_EMBEDDING_TOKENS_PER_SECOND isn't set
+const ESTIMATED_TOKENS_PER_SECOND: f64 = 10000.0;
+// Rough size of a chunk's payload and index entry in the vector store
+const ESTIMATED_PAYLOAD_BYTES: u64 = 512;
+
+// What importing a set of rows would take, from chunking them without embedding
+#[derive(Debug, Default)]
+pub struct ImportEstimate {
+    pub rows: u64,
+    pub characters: u64,
+    pub chunks: u64,
+    pub tokens: u64,
+}
+
+impl ImportEstimate {
+    // Chunks and tokenizes the documents the way an import would
+    pub async fn of_documents(documents: &[VectorDbDocument]) -> Result<Self, EmbeddingError> {
+        let extracted = extract_file_texts(documents.to_vec()).await?;
+        for (document, err) in &extracted.failed {
+            log::warn!(
+                "Leaving {} {} out of the estimate: {}",
+                document.table_name,
+                document.id,
+                err
+            );
+        }
+        let documents = &extracted.documents;
+        let frames = load_frames(documents).await?;
+        let cleaned = clean_documents(documents);
+        let mut estimate = ImportEstimate::default();
+
+        for document in documents {
+            estimate.rows += 1;
+            estimate.characters += document.content.chars().count() as u64;
+
+            let key = document_key(document);
+            let frame = frames.get(&key);
+            let text = cleaned.get(&key).map_or(&document.content, |cleaned| &cleaned.text);
+            let mut chunk_iterator = StringChunkIterator::new(text, chunk_token_budget(frame))
+                .with_language(detect_language(text));
+            while let Some(chunk) = chunk_iterator.next().await {
+                let chunk = chunk?;
+                if chunk.text.is_empty() {
+                    continue;
+                }
+                estimate.chunks += 1;
+                estimate.tokens +=
+                    (chunk.token_count + frame.map_or(0, |frame| frame.token_count)) as u64;
+            }
+        }
+
+        Ok(estimate)
+    }
+
+    // Each window is chunked on its own, so chunks at the window edges are counted a
+    // little differently from an import, which carries them over to the next window
+    pub async fn of_streamed_document<F, Fut>(
+        document: VectorDbDocument,
+        window_chars: usize,
+        mut read_window: F,
+    ) -> Result<Self, EmbeddingError>
+    where
+        F: FnMut(usize) -> Fut,
+        Fut: Future<Output = Result<Option<String>, EmbeddingError>>,
+    {
+        let mut estimate = ImportEstimate::default();
+        let mut read_chars = 0;
+
+        loop {
+            let window = read_window(read_chars).await?.unwrap_or_default();
+            let window_length = window.chars().count();
+            read_chars += window_length;
+
+            let window_document = VectorDbDocument {
+                id: document.id,
+                table_name: document.table_name.clone(),
+                content: window,
+                user_id: document.user_id,
+                page_offsets: Vec::new(),
+            };
+            estimate.add(&ImportEstimate::of_documents(&[window_document]).await?);
+
+            if window_length < window_chars {
+                break;
+            }
+        }
+        estimate.rows = 1;
+
+        Ok(estimate)
+    }
+
+    pub fn add(&mut self, other: &ImportEstimate) {
+        self.rows += other.rows;
+        self.characters += other.characters;
+        self.chunks += other.chunks;
+        self.tokens += other.tokens;
+    }
+
+    // Every provider embeds every chunk
+    pub fn embedding_seconds(&self) -> f64 {
+        let tokens_per_second = std::env::var("MAX_EMBEDDING_TOKENS_PER_SECOND")
+            .ok()
+            .and_then(|s| s.parse::<f64>().ok())
+            .filter(|rate| *rate > 0.0)
+            .unwrap_or(ESTIMATED_TOKENS_PER_SECOND);
+        let providers = get_config().embedding.vector_providers().len();
+
+        (self.tokens * providers as u64) as f64 / tokens_per_second
+    }
+
+    // None while a provider's dimension isn't configured, since only the model knows it
+    pub fn storage_bytes(&self) -> Option<u64> {
+        let vector_bytes = get_config()
+            .embedding
+            .vector_providers()
+            .iter()
+            .map(|provider| provider.dimension.map(|dimension| dimension as u64 * 4))
+            .sum::<Option<u64>>()?;
+
+        Some(self.chunks * (vector_bytes + ESTIMATED_PAYLOAD_BYTES))
+    }
+}
+
+impl fmt::Display for ImportEstimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let seconds = self.embedding_seconds();
+        write!(
+            f,
+            "{} rows, {} characters, {} chunks, {} tokens; about {:.1} hours of embedding",
+            self.rows,
+            self.characters,
+            self.chunks,
+            self.tokens,
+            seconds / 3600.0
+        )?;
+
+        match self.storage_bytes() {
+            Some(bytes) => write!(f, " and {:.2} GB of vector storage", bytes as f64 / 1e9),
+            None => write!(f, " (set `dimension` to estimate vector storage)"),
+        }
+    }
+}
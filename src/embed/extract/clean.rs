@@ -0,0 +1,596 @@
+use std::collections::HashMap;
+
+use markup5ever::data::NAMED_ENTITIES;
+
+use crate::embed::{
+    document_text::{document_key, DocumentKey},
+    tables::embeddable_table,
+};
+use crate::grpc::server::vecembed_rpc::VectorDbDocument;
+
+// Never rendered as text
+const SKIPPED_ELEMENTS: &[&str] = &["head", "script", "style", "noscript", "template", "svg"];
+// Start on a line of their own
+const BLOCK_ELEMENTS: &[&str] = &[
+    "address", "article", "aside", "blockquote", "br", "dd", "div", "dl", "dt", "figcaption",
+    "figure", "footer", "form", "h1", "h2", "h3", "h4", "h5", "h6", "header", "hr", "li", "main",
+    "nav", "ol", "p", "pre", "section", "table", "td", "th", "tr", "ul",
+];
+// Followed by a blank line, so they're counted as paragraphs of their own
+const PARAGRAPH_ELEMENTS: &[&str] = &[
+    "blockquote", "dl", "figure", "h1", "h2", "h3", "h4", "h5", "h6", "ol", "p", "pre", "table",
+    "ul",
+];
+// Have no closing tag
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "track", "wbr",
+];
+// Whatever's in them is never text
+const RAW_TEXT_ELEMENTS: &[&str] = &["script", "style"];
+// Never part of a page's content
+const BOILERPLATE_ELEMENTS: &[&str] = &[
+    "nav", "aside", "form", "button", "iframe", "select", "dialog", "menu",
+];
+// Boilerplate around the main content, but an article's own header and footer aren't
+const PAGE_ELEMENTS: &[&str] = &["header", "footer"];
+const BOILERPLATE_ROLES: &[&str] = &[
+    "navigation", "banner", "contentinfo", "complementary", "dialog", "alertdialog", "search",
+];
+// Hold the whole page or its content, so whatever their class says they're never skipped
+const CONTAINER_ELEMENTS: &[&str] = &["html", "body", "main", "article"];
+// Words in a class or id that give away menus, banners and the like
+const BOILERPLATE_MARKERS: &[&str] = &[
+    "cookie", "cookies", "consent", "gdpr", "banner", "navbar", "nav", "navigation", "menu",
+    "sidebar", "footer", "breadcrumb", "breadcrumbs", "advert", "ad", "ads", "newsletter",
+    "popup", "modal", "social", "share", "related", "comments",
+];
+
+// Text cleaned out of an HTML body, with where each part of it is in the body, so offsets
+// into the text can be turned into offsets into the body
+#[derive(Debug, Default)]
+pub struct CleanedText {
+    pub text: String,
+    // (offset in text, offset in body) wherever the two stop moving together
+    offsets: Vec<(usize, usize)>,
+    body_length: usize,
+}
+
+impl CleanedText {
+    // Where the text from `offset` on starts in the body
+    pub fn body_start(&self, offset: usize) -> usize {
+        self.body_offset(self.offsets.partition_point(|(start, _)| *start <= offset), offset)
+    }
+
+    // Where the text up to `offset` ends in the body
+    pub fn body_end(&self, offset: usize) -> usize {
+        if offset == 0 {
+            return self.body_start(0);
+        }
+        self.body_offset(self.offsets.partition_point(|(start, _)| *start < offset), offset)
+    }
+
+    fn body_offset(&self, index: usize, offset: usize) -> usize {
+        if index == 0 {
+            return 0;
+        }
+        let (text_start, body_start) = self.offsets[index - 1];
+        // Newlines and heading marks added to the text stand for the tag they were made from
+        let next_body_start = self
+            .offsets
+            .get(index)
+            .map_or(self.body_length, |(_, body_start)| *body_start);
+
+        (body_start + (offset - text_start)).min(next_body_start)
+    }
+
+    fn map(&mut self, at: usize) {
+        let offset = self.text.len();
+        match self.offsets.last() {
+            Some(&(text_start, body_start)) if body_start + (offset - text_start) == at => {}
+            _ => self.offsets.push((offset, at)),
+        }
+    }
+
+    // Whitespace is collapsed to single spaces, and dropped at the start of a line
+    fn push_char(&mut self, c: char, at: usize) {
+        let c = if c.is_whitespace() {
+            if self.text.is_empty() || self.text.ends_with(char::is_whitespace) {
+                return;
+            }
+            ' '
+        } else {
+            c
+        };
+        self.map(at);
+        self.text.push(c);
+    }
+
+    fn insert(&mut self, text: &str, at: usize) {
+        self.map(at);
+        self.text.push_str(text);
+    }
+
+    fn end_line(&mut self, at: usize) {
+        let trimmed = self.text.trim_end_matches(' ').len();
+        self.text.truncate(trimmed);
+        self.offsets.retain(|(start, _)| *start < trimmed);
+        if !self.text.is_empty() && !self.text.ends_with('\n') {
+            self.insert("\n", at);
+        }
+    }
+
+    fn end_paragraph(&mut self, at: usize) {
+        self.end_line(at);
+        if !self.text.is_empty() && !self.text.ends_with("\n\n") {
+            self.insert("\n", at);
+        }
+    }
+}
+
+enum Token<'a> {
+    Open {
+        name: String,
+        attributes: &'a str,
+        self_closing: bool,
+        at: usize,
+    },
+    Close {
+        name: String,
+        at: usize,
+    },
+    Text {
+        text: &'a str,
+        at: usize,
+    },
+}
+
+// Lowercased tag name at the start of `rest`
+fn tag_name(rest: &str) -> String {
+    rest.chars()
+        .take_while(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == ':')
+        .collect::<String>()
+        .to_ascii_lowercase()
+}
+
+// End of the tag starting at `start`, past its `>`; quoted attribute values can hold `>`
+fn tag_end(body: &str, start: usize) -> usize {
+    let mut quote = None;
+    for (index, c) in body[start..].char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(open), _) if c == open => quote = None,
+            (None, '>') => return start + index + 1,
+            _ => {}
+        }
+    }
+    body.len()
+}
+
+fn find_case_insensitive(haystack: &str, needle: &str) -> Option<usize> {
+    haystack
+        .as_bytes()
+        .windows(needle.len())
+        .position(|window| window.eq_ignore_ascii_case(needle.as_bytes()))
+}
+
+// A forgiving tokenizer: markup it can't make sense of is read as text, and the
+// contents of scripts and styles are skipped
+fn tokenize(body: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut text_start = 0;
+    let mut index = 0;
+
+    while let Some(found) = body[index..].find('<') {
+        let start = index + found;
+        let rest = &body[start..];
+        let end = if rest.starts_with("<!--") {
+            rest.find("-->").map_or(body.len(), |end| start + end + 3)
+        } else if rest.starts_with("<!") || rest.starts_with("<?") {
+            rest.find('>').map_or(body.len(), |end| start + end + 1)
+        } else if rest[1..].starts_with(|c: char| c.is_ascii_alphabetic())
+            || rest[1..].starts_with('/')
+        {
+            tag_end(body, start)
+        } else {
+            index = start + 1;
+            continue;
+        };
+
+        if text_start < start {
+            tokens.push(Token::Text {
+                text: &body[text_start..start],
+                at: text_start,
+            });
+        }
+        text_start = end;
+        index = end;
+
+        // Comments, doctypes and processing instructions
+        if rest.starts_with("<!") || rest.starts_with("<?") {
+            continue;
+        }
+        let tag = body[start + 1..end].trim_end_matches('>');
+
+        if let Some(closing) = tag.strip_prefix('/') {
+            tokens.push(Token::Close {
+                name: tag_name(closing),
+                at: start,
+            });
+            continue;
+        }
+
+        let name = tag_name(tag);
+        let attributes = &tag[name.len()..];
+        let self_closing = attributes.trim_end().ends_with('/');
+        let raw_text = RAW_TEXT_ELEMENTS.contains(&name.as_str()) && !self_closing;
+        tokens.push(Token::Open {
+            name: name.clone(),
+            attributes,
+            self_closing,
+            at: start,
+        });
+
+        if raw_text {
+            let close = find_case_insensitive(&body[end..], &format!("</{}", name))
+                .map_or(body.len(), |close| end + close);
+            tokens.push(Token::Close { name, at: close });
+            index = tag_end(body, close);
+            text_start = index;
+        }
+    }
+
+    if text_start < body.len() {
+        tokens.push(Token::Text {
+            text: &body[text_start..],
+            at: text_start,
+        });
+    }
+
+    tokens
+}
+
+// Value of the attribute `name`, empty for ones without a value
+fn attribute<'a>(attributes: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = attributes;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == '/');
+        if rest.is_empty() {
+            return None;
+        }
+
+        let name_end = rest
+            .find(|c: char| c.is_whitespace() || c == '=' || c == '/')
+            .unwrap_or(rest.len());
+        let attribute_name = &rest[..name_end];
+        rest = rest[name_end..].trim_start();
+
+        let mut value = "";
+        if let Some(after_equals) = rest.strip_prefix('=') {
+            let after_equals = after_equals.trim_start();
+            let (found, remaining) = match after_equals.chars().next() {
+                Some(quote @ ('"' | '\'')) => {
+                    let quoted = &after_equals[1..];
+                    let end = quoted.find(quote).unwrap_or(quoted.len());
+                    (&quoted[..end], &quoted[(end + 1).min(quoted.len())..])
+                }
+                _ => {
+                    let end = after_equals
+                        .find(char::is_whitespace)
+                        .unwrap_or(after_equals.len());
+                    (&after_equals[..end], &after_equals[end..])
+                }
+            };
+            value = found;
+            rest = remaining;
+        }
+
+        if attribute_name.eq_ignore_ascii_case(name) {
+            return Some(value);
+        }
+    }
+}
+
+// h1 to h6, which are written as Markdown headings
+fn heading_level(name: &str) -> Option<usize> {
+    let level = name.strip_prefix('h')?.parse().ok()?;
+    (1..=6).contains(&level).then_some(level)
+}
+
+fn is_boilerplate(name: &str, attributes: &str, in_main_content: bool) -> bool {
+    if CONTAINER_ELEMENTS.contains(&name) {
+        return false;
+    }
+    if BOILERPLATE_ELEMENTS.contains(&name) || (!in_main_content && PAGE_ELEMENTS.contains(&name)) {
+        return true;
+    }
+    if attribute(attributes, "hidden").is_some()
+        || attribute(attributes, "aria-hidden") == Some("true")
+    {
+        return true;
+    }
+    if attribute(attributes, "role")
+        .is_some_and(|role| BOILERPLATE_ROLES.contains(&role.to_ascii_lowercase().as_str()))
+    {
+        return true;
+    }
+
+    ["class", "id"].iter().any(|attribute_name| {
+        attribute(attributes, attribute_name).is_some_and(|value| {
+            value
+                .to_ascii_lowercase()
+                .split(|c: char| !c.is_ascii_alphanumeric())
+                .any(|word| BOILERPLATE_MARKERS.contains(&word))
+        })
+    })
+}
+
+// The text a `&...;` reference at the start of `text` stands for, and its length
+fn entity(text: &str) -> Option<(String, usize)> {
+    let (end, _) = text.char_indices().take(40).find(|(_, c)| *c == ';')?;
+    let name = &text[1..end];
+    let decoded = match name.strip_prefix('#') {
+        Some(number) => {
+            let code = match number.strip_prefix(['x', 'X']) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => number.parse().ok()?,
+            };
+            char::from_u32(code)?.to_string()
+        }
+        None => {
+            let (first, second) = NAMED_ENTITIES.get(&text[1..=end])?;
+            [*first, *second]
+                .into_iter()
+                .filter(|code| *code != 0)
+                .filter_map(char::from_u32)
+                .collect()
+        }
+    };
+
+    Some((decoded, end + 1))
+}
+
+fn push_text(cleaned: &mut CleanedText, text: &str, at: usize) {
+    let mut index = 0;
+    while let Some(c) = text[index..].chars().next() {
+        let decoded = match c {
+            '&' => entity(&text[index..]),
+            _ => None,
+        };
+        match decoded {
+            Some((decoded, length)) => {
+                for c in decoded.chars() {
+                    cleaned.push_char(c, at + index);
+                }
+                index += length;
+            }
+            None => {
+                cleaned.push_char(c, at + index);
+                index += c.len_utf8();
+            }
+        }
+    }
+}
+
+struct OpenElement {
+    name: String,
+    skipped: bool,
+    main_content: bool,
+}
+
+// Keeps the text of a page's main content: only what's in `<main>` or `<article>` when
+// the page has them, and never navigation, banners, forms, scripts or hidden elements.
+// Headings are written as Markdown headings and blocks separated by newlines.
+pub fn clean_html(body: &str) -> CleanedText {
+    html_text(body, true)
+}
+
+// All of a page's text, boilerplate included; only scripts, styles and the like are left out
+pub fn uncleaned_html(body: &str) -> CleanedText {
+    html_text(body, false)
+}
+
+fn html_text(body: &str, drop_boilerplate: bool) -> CleanedText {
+    let tokens = tokenize(body);
+    let has_main_content = drop_boilerplate
+        && tokens.iter().any(|token| {
+            matches!(token, Token::Open { name, .. } if name == "main" || name == "article")
+        });
+
+    let mut cleaned = CleanedText {
+        body_length: body.len(),
+        ..Default::default()
+    };
+    let mut open: Vec<OpenElement> = Vec::new();
+    let mut skipped_depth = 0;
+    let mut main_content_depth = 0;
+
+    for token in tokens {
+        let in_main_content = !has_main_content || main_content_depth > 0;
+        let emitting = skipped_depth == 0 && in_main_content;
+
+        match token {
+            Token::Text { text, at } => {
+                if emitting {
+                    push_text(&mut cleaned, text, at);
+                }
+            }
+            Token::Open {
+                name,
+                attributes,
+                self_closing,
+                at,
+            } => {
+                if VOID_ELEMENTS.contains(&name.as_str()) || self_closing {
+                    if emitting && BLOCK_ELEMENTS.contains(&name.as_str()) {
+                        cleaned.end_line(at);
+                    }
+                    continue;
+                }
+
+                let main_content = name == "main" || name == "article";
+                let skipped = SKIPPED_ELEMENTS.contains(&name.as_str())
+                    || RAW_TEXT_ELEMENTS.contains(&name.as_str())
+                    || (drop_boilerplate && is_boilerplate(&name, attributes, main_content_depth > 0));
+                let starts_emitting = skipped_depth == 0 && !skipped && (in_main_content || main_content);
+
+                if starts_emitting && BLOCK_ELEMENTS.contains(&name.as_str()) {
+                    cleaned.end_line(at);
+                }
+                if starts_emitting {
+                    if let Some(level) = heading_level(&name) {
+                        cleaned.insert(&format!("{} ", "#".repeat(level)), at);
+                    }
+                }
+
+                skipped_depth += skipped as usize;
+                main_content_depth += main_content as usize;
+                open.push(OpenElement {
+                    name,
+                    skipped,
+                    main_content,
+                });
+            }
+            Token::Close { name, at } => {
+                // Elements left open inside it, like `<p>` and `<li>`, close with it
+                let Some(position) = open.iter().rposition(|element| element.name == name) else {
+                    continue;
+                };
+                for element in open.drain(position..) {
+                    skipped_depth -= element.skipped as usize;
+                    main_content_depth -= element.main_content as usize;
+                }
+
+                if emitting && PARAGRAPH_ELEMENTS.contains(&name.as_str()) {
+                    cleaned.end_paragraph(at);
+                } else if emitting && BLOCK_ELEMENTS.contains(&name.as_str()) {
+                    cleaned.end_line(at);
+                }
+            }
+        }
+    }
+
+    let trimmed = cleaned.text.trim_end().len();
+    cleaned.text.truncate(trimmed);
+    cleaned.offsets.retain(|(start, _)| *start < trimmed);
+
+    cleaned
+}
+
+// Crawled bodies are often fragments without an `<html>` element
+fn looks_like_html(content: &str) -> bool {
+    let content = content.trim_start();
+    content.starts_with('<') && content.contains('>')
+}
+
+// The cleaned texts of the documents whose tables ask for their HTML to be cleaned.
+// Documents cleaning leaves no text in are embedded uncleaned instead.
+pub fn clean_documents(documents: &[VectorDbDocument]) -> HashMap<DocumentKey, CleanedText> {
+    documents
+        .iter()
+        .filter(|document| {
+            embeddable_table(&document.table_name).is_some_and(|table| table.clean_html)
+                && looks_like_html(&document.content)
+        })
+        .filter_map(|document| {
+            let cleaned = clean_html(&document.content);
+            if cleaned.text.is_empty() {
+                log::warn!(
+                    "Cleaning the HTML of {} {} left no text, embedding it uncleaned",
+                    document.table_name,
+                    document.id
+                );
+                return None;
+            }
+
+            Some((document_key(document), cleaned))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clean_html_keeps_only_the_main_content() {
+        let body = "<html><head><title>Page</title></head><body>\
+            <nav><a href=\"/\">Home</a></nav>\
+            <div class=\"cookie-banner\">We use cookies</div>\
+            <main><h1>Title</h1><p>First &amp; second.</p><script>var x = 1;</script>\
+            <p>Third</p></main>\
+            <footer>All rights reserved</footer></body></html>";
+
+        let cleaned = clean_html(body);
+
+        assert_eq!(cleaned.text, "# Title\n\nFirst & second.\n\nThird");
+    }
+
+    #[test]
+    fn clean_html_drops_boilerplate_of_pages_without_main_content() {
+        let body = "<div id=\"sidebar\">Links</div><header>Site</header>\
+            <p hidden>Hidden</p><p>Kept   text\n  here</p><div role=\"search\">Search</div>";
+
+        let cleaned = clean_html(body);
+
+        assert_eq!(cleaned.text, "Kept text here");
+    }
+
+    #[test]
+    fn clean_html_never_skips_the_page_or_its_content_for_their_class() {
+        let body = "<html><body class=\"has-sidebar\"><nav>Menu</nav>\
+            <article class=\"post social-share\" role=\"complementary\"><p>Kept</p></article>\
+            </body></html>";
+
+        let cleaned = clean_html(body);
+
+        assert_eq!(cleaned.text, "Kept");
+    }
+
+    #[test]
+    fn clean_documents_leaves_html_that_cleans_to_nothing_uncleaned() {
+        let document = |id, content: &str| VectorDbDocument {
+            id,
+            table_name: "contents".to_string(),
+            content: content.to_string(),
+            ..Default::default()
+        };
+        let documents = [
+            document(1, "<nav>Only a menu</nav>"),
+            document(2, "<nav>Menu</nav><p>Text</p>"),
+        ];
+
+        let cleaned = clean_documents(&documents);
+
+        assert!(!cleaned.contains_key(&("contents".to_string(), 1)));
+        assert_eq!(cleaned[&("contents".to_string(), 2)].text, "Text");
+    }
+
+    #[test]
+    fn cleaned_offsets_point_into_the_body() {
+        let body = "<nav>Menu</nav><p>First &amp; <b>second</b> paragraph.</p><p>Another one</p>";
+        let cleaned = clean_html(body);
+
+        for word in ["First", "second", "paragraph.", "Another one"] {
+            let start = cleaned.text.find(word).unwrap();
+            let end = start + word.len();
+            let body_start = cleaned.body_start(start);
+            let body_end = cleaned.body_end(end);
+
+            assert_eq!(&body[body_start..body_end], word);
+        }
+    }
+
+    #[test]
+    fn cleaned_offsets_span_the_markup_between_them() {
+        let body = "<p>First &amp; <b>second</b></p>";
+        let cleaned = clean_html(body);
+        assert_eq!(cleaned.text, "First & second");
+
+        let body_start = cleaned.body_start(0);
+        let body_end = cleaned.body_end(cleaned.text.len());
+
+        assert_eq!(&body[body_start..body_end], "First &amp; <b>second");
+        assert_eq!(cleaned.body_end(0), body_start);
+    }
+}
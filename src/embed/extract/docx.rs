@@ -0,0 +1,169 @@
+use std::io::{Cursor, Read};
+
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+
+use super::ExtractedText;
+use crate::embed::errors::EmbeddingError;
+
+fn docx_error(err: impl std::fmt::Display) -> EmbeddingError {
+    EmbeddingError::Extraction(format!("DOCX: {}", err))
+}
+
+fn is_page_break(element: &BytesStart) -> bool {
+    matches!(
+        element.try_get_attribute("w:type"),
+        Ok(Some(attribute)) if attribute.value.as_ref() == b"page"
+    )
+}
+
+// Heading paragraphs are styled "Heading1" to "Heading6", or "Title"
+fn heading_level(element: &BytesStart) -> Option<usize> {
+    let attribute = element.try_get_attribute("w:val").ok()??;
+    match attribute.value.as_ref() {
+        b"Title" => Some(1),
+        style => std::str::from_utf8(style.strip_prefix(b"Heading")?)
+            .ok()?
+            .parse()
+            .ok()
+            .filter(|level| (1..=6).contains(level)),
+    }
+}
+
+// Paragraphs are separated by a blank line, headings written as Markdown ones, so the
+// chunker can tell which paragraph and section each chunk is in
+fn end_paragraph(text: &mut String) {
+    if !text.is_empty() && !text.ends_with("\n\n") {
+        text.push_str(if text.ends_with('\n') { "\n" } else { "\n\n" });
+    }
+}
+
+// Word only knows where pages end once it has laid the document out, and records that
+// as `lastRenderedPageBreak`; explicit page breaks count as well
+fn push_page_break(extracted: &mut ExtractedText) {
+    let offset = extracted.text.len() as u64;
+    if offset > 0 && extracted.page_offsets.last() != Some(&offset) {
+        extracted.page_offsets.push(offset);
+    }
+}
+
+pub fn extract(bytes: &[u8]) -> Result<ExtractedText, EmbeddingError> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(bytes)).map_err(docx_error)?;
+    let mut xml = String::new();
+    archive
+        .by_name("word/document.xml")
+        .map_err(docx_error)?
+        .read_to_string(&mut xml)
+        .map_err(docx_error)?;
+
+    let mut reader = Reader::from_str(&xml);
+    let mut extracted = ExtractedText::default();
+    let mut in_text = false;
+
+    loop {
+        match reader.read_event().map_err(docx_error)? {
+            Event::Start(element) if element.name().as_ref() == b"w:t" => in_text = true,
+            Event::End(element) => match element.name().as_ref() {
+                b"w:t" => in_text = false,
+                b"w:p" => end_paragraph(&mut extracted.text),
+                _ => {}
+            },
+            Event::Empty(element) => match element.name().as_ref() {
+                b"w:p" => end_paragraph(&mut extracted.text),
+                b"w:pStyle" => {
+                    if let Some(level) = heading_level(&element) {
+                        extracted.text.push_str(&format!("{} ", "#".repeat(level)));
+                    }
+                }
+                b"w:tab" => extracted.text.push('\t'),
+                b"w:br" if is_page_break(&element) => push_page_break(&mut extracted),
+                b"w:br" | b"w:cr" => extracted.text.push('\n'),
+                b"w:lastRenderedPageBreak" => push_page_break(&mut extracted),
+                _ => {}
+            },
+            Event::Text(text) if in_text => {
+                extracted.text.push_str(&text.unescape().map_err(docx_error)?)
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok(extracted)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use zip::write::SimpleFileOptions;
+
+    use super::*;
+
+    fn docx(body: &str) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options =
+            SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        writer.start_file("word/document.xml", options).unwrap();
+        write!(
+            writer,
+            r#"<?xml version="1.0" encoding="UTF-8"?><w:document xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main"><w:body>{}</w:body></w:document>"#,
+            body
+        )
+        .unwrap();
+
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn extract_separates_paragraphs_and_marks_headings() {
+        let bytes = docx(concat!(
+            r#"<w:p><w:pPr><w:pStyle w:val="Heading2"/></w:pPr><w:r><w:t>Rust</w:t></w:r></w:p>"#,
+            r#"<w:p><w:r><w:t xml:space="preserve">Fast </w:t></w:r><w:r><w:t>&amp; safe.</w:t></w:r></w:p>"#,
+            r#"<w:p/>"#,
+            r#"<w:p><w:r><w:t>Second</w:t><w:tab/><w:t>paragraph.</w:t></w:r></w:p>"#,
+        ));
+
+        let extracted = extract(&bytes).unwrap();
+
+        assert_eq!(
+            extracted.text,
+            "## Rust\n\nFast & safe.\n\nSecond\tparagraph.\n\n"
+        );
+        assert!(extracted.page_offsets.is_empty());
+    }
+
+    #[test]
+    fn extract_records_page_breaks() {
+        let bytes = docx(concat!(
+            r#"<w:p><w:r><w:t>First page.</w:t></w:r></w:p>"#,
+            r#"<w:p><w:r><w:br w:type="page"/><w:t>Second page.</w:t></w:r></w:p>"#,
+            r#"<w:p><w:r><w:lastRenderedPageBreak/><w:t>Third page.</w:t></w:r></w:p>"#,
+        ));
+
+        let extracted = extract(&bytes).unwrap();
+
+        let offsets: Vec<usize> = extracted
+            .page_offsets
+            .iter()
+            .map(|offset| *offset as usize)
+            .collect();
+        assert_eq!(offsets.len(), 2);
+        assert!(extracted.text[offsets[0]..].starts_with("Second page."));
+        assert!(extracted.text[offsets[1]..].starts_with("Third page."));
+    }
+
+    #[test]
+    fn extract_rejects_zips_without_a_document() {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        writer
+            .start_file("other.xml", SimpleFileOptions::default())
+            .unwrap();
+        let bytes = writer.finish().unwrap().into_inner();
+
+        assert!(matches!(
+            extract(&bytes),
+            Err(EmbeddingError::Extraction(_))
+        ));
+    }
+}
@@ -0,0 +1,39 @@
+use super::{
+    clean::{clean_html, uncleaned_html},
+    ExtractedText,
+};
+
+// Uploaded pages are cleaned like crawled ones, and kept whole should that leave nothing
+pub fn extract(bytes: &[u8]) -> ExtractedText {
+    let body = String::from_utf8_lossy(bytes);
+    let mut cleaned = clean_html(&body);
+    if cleaned.text.is_empty() {
+        cleaned = uncleaned_html(&body);
+    }
+
+    ExtractedText {
+        text: cleaned.text,
+        page_offsets: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extract_leaves_out_boilerplate_of_uploaded_pages() {
+        let page = b"<html><body><nav>Home | About</nav>\
+            <div class=\"cookie-consent\">Accept cookies</div>\
+            <h1>Report</h1><p>Caf&eacute; sales &amp; costs</p></body></html>";
+
+        assert_eq!(extract(page).text, "# Report\n\nCaf\u{e9} sales & costs");
+    }
+
+    #[test]
+    fn extract_keeps_pages_that_are_all_boilerplate_whole() {
+        let page = b"<html><body><nav>Home | About</nav></body></html>";
+
+        assert_eq!(extract(page).text, "Home | About");
+    }
+}
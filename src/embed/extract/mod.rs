@@ -0,0 +1,421 @@
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+
+use sea_orm::{ConnectionTrait, DatabaseBackend, Statement, Value};
+
+use super::{
+    document_text::document_key,
+    errors::EmbeddingError,
+    instances::get_db_instance,
+    tables::{embeddable_table, EmbeddableTable, FileColumns},
+};
+use crate::grpc::server::vecembed_rpc::VectorDbDocument;
+
+mod clean;
+mod docx;
+mod html;
+mod pdf;
+
+pub use clean::{clean_documents, CleanedText};
+
+// A file's text, with where each of its pages starts
+#[derive(Debug, Default)]
+pub struct ExtractedText {
+    pub text: String,
+    // Byte offsets into the text where each page after the first starts
+    pub page_offsets: Vec<u64>,
+}
+
+impl ExtractedText {
+    fn from_pages(pages: Vec<String>) -> Self {
+        let mut extracted = ExtractedText::default();
+        for (index, page) in pages.iter().enumerate() {
+            if index > 0 {
+                if !extracted.text.is_empty() && !extracted.text.ends_with('\n') {
+                    extracted.text.push('\n');
+                }
+                extracted.page_offsets.push(extracted.text.len() as u64);
+            }
+            extracted.text.push_str(page);
+        }
+
+        extracted
+    }
+}
+
+// The 1-based page the byte at `offset` is on
+pub fn page_at(page_offsets: &[u64], offset: usize) -> usize {
+    page_offsets.partition_point(|start| *start <= offset as u64) + 1
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileKind {
+    Pdf,
+    Docx,
+    Html,
+    Text,
+}
+
+impl FileKind {
+    pub fn from_mime(mime: &str) -> Option<Self> {
+        let mime = mime.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+        match mime.as_str() {
+            "application/pdf" => Some(FileKind::Pdf),
+            "application/vnd.openxmlformats-officedocument.wordprocessingml.document" => {
+                Some(FileKind::Docx)
+            }
+            "text/html" | "application/xhtml+xml" => Some(FileKind::Html),
+            "text/plain" | "text/markdown" | "text/csv" => Some(FileKind::Text),
+            _ => None,
+        }
+    }
+
+    fn from_extension(path: &str) -> Option<Self> {
+        let extension = Path::new(path).extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "pdf" => Some(FileKind::Pdf),
+            "docx" => Some(FileKind::Docx),
+            "html" | "htm" | "xhtml" => Some(FileKind::Html),
+            "txt" | "md" | "markdown" | "csv" => Some(FileKind::Text),
+            _ => None,
+        }
+    }
+
+    fn sniff(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(b"%PDF-") {
+            return Some(FileKind::Pdf);
+        }
+        // Any zip could be a DOCX; extraction fails on the ones that aren't
+        if bytes.starts_with(b"PK\x03\x04") {
+            return Some(FileKind::Docx);
+        }
+        let head = String::from_utf8_lossy(&bytes[..bytes.len().min(512)]).to_ascii_lowercase();
+        if head.trim_start().starts_with("<!doctype html") || head.contains("<html") {
+            return Some(FileKind::Html);
+        }
+
+        None
+    }
+
+    // A MIME type given with the file wins over its extension, which wins over its contents
+    pub fn detect(path: &str, mime: Option<&str>, bytes: &[u8]) -> Option<Self> {
+        mime.and_then(FileKind::from_mime)
+            .or_else(|| FileKind::from_extension(path))
+            .or_else(|| FileKind::sniff(bytes))
+    }
+}
+
+pub fn extract_text(kind: FileKind, bytes: &[u8]) -> Result<ExtractedText, EmbeddingError> {
+    match kind {
+        FileKind::Pdf => pdf::extract(bytes),
+        FileKind::Docx => docx::extract(bytes),
+        FileKind::Html => Ok(html::extract(bytes)),
+        FileKind::Text => Ok(ExtractedText {
+            text: String::from_utf8_lossy(bytes).trim_start_matches('\u{feff}').to_string(),
+            page_offsets: Vec::new(),
+        }),
+    }
+}
+
+// Where uploaded files are read from
+enum FileStorage {
+    Local(PathBuf),
+    // Base URL of an S3-compatible bucket, or anything else serving the files over HTTP
+    Http(String),
+}
+
+fn file_storage() -> Option<FileStorage> {
+    if let Ok(url) = std::env::var("FILE_STORAGE_URL") {
+        return Some(FileStorage::Http(url.trim_end_matches('/').to_string()));
+    }
+    std::env::var("FILE_STORAGE_PATH")
+        .ok()
+        .map(|path| FileStorage::Local(PathBuf::from(path)))
+}
+
+// Without file storage, file tables are embedded from their text column as before
+pub fn file_storage_configured() -> bool {
+    file_storage().is_some()
+}
+
+async fn read_file(storage: &FileStorage, path: &str) -> Result<Vec<u8>, EmbeddingError> {
+    // Paths come from the database, but must still stay inside the storage
+    let relative = Path::new(path.trim_start_matches('/'));
+    if relative
+        .components()
+        .any(|component| !matches!(component, Component::Normal(_)))
+    {
+        return Err(EmbeddingError::Extraction(format!("invalid file path `{}`", path)));
+    }
+
+    match storage {
+        FileStorage::Local(root) => {
+            let full_path = root.join(relative);
+            tokio::task::spawn_blocking(move || std::fs::read(&full_path))
+                .await?
+                .map_err(|e| EmbeddingError::Extraction(format!("reading `{}`: {}", path, e)))
+        }
+        FileStorage::Http(base_url) => {
+            let url = format!("{}/{}", base_url, relative.to_string_lossy());
+            let response = reqwest::get(&url)
+                .await
+                .and_then(|response| response.error_for_status())
+                .map_err(|e| EmbeddingError::Extraction(format!("fetching `{}`: {}", path, e)))?;
+            let bytes = response
+                .bytes()
+                .await
+                .map_err(|e| EmbeddingError::Extraction(format!("fetching `{}`: {}", path, e)))?;
+
+            Ok(bytes.to_vec())
+        }
+    }
+}
+
+async fn query_column(
+    table: &EmbeddableTable,
+    id: i64,
+    column: &str,
+) -> Result<Option<String>, EmbeddingError> {
+    let db = get_db_instance().await;
+    let row = db
+        .query_one(Statement::from_sql_and_values(
+            DatabaseBackend::MySql,
+            format!(
+                "SELECT CAST({} AS CHAR) AS value FROM {} WHERE {} = ?",
+                column, table.name, table.id_column
+            ),
+            [id.into()],
+        ))
+        .await?;
+
+    Ok(match row {
+        Some(row) => row.try_get::<Option<String>>("", "value")?,
+        None => None,
+    })
+}
+
+// Where a row's file is and what type it was stored as
+struct FileRow {
+    path: Option<String>,
+    mime: Option<String>,
+}
+
+// The file rows of a table's documents, by id
+async fn query_file_rows(
+    table: &EmbeddableTable,
+    file: &FileColumns,
+    ids: &[i64],
+) -> Result<HashMap<i64, FileRow>, EmbeddingError> {
+    let mime = file
+        .mime_column
+        .map(|column| format!("CAST({} AS CHAR)", column))
+        .unwrap_or("NULL".to_string());
+    let id_list = vec!["?"; ids.len()].join(", ");
+
+    let db = get_db_instance().await;
+    let rows = db
+        .query_all(Statement::from_sql_and_values(
+            DatabaseBackend::MySql,
+            format!(
+                "SELECT CAST({id} AS SIGNED) AS id, CAST({path} AS CHAR) AS path, {mime} AS mime
+                FROM {table} WHERE {id} IN ({id_list})",
+                id = table.id_column,
+                path = file.path_column,
+                table = table.name,
+            ),
+            ids.iter().map(|id| Value::from(*id)).collect::<Vec<_>>(),
+        ))
+        .await?;
+
+    let mut file_rows = HashMap::new();
+    for row in rows {
+        file_rows.insert(
+            row.try_get::<i64>("", "id")?,
+            FileRow {
+                path: row.try_get("", "path")?,
+                mime: row.try_get("", "mime")?,
+            },
+        );
+    }
+
+    Ok(file_rows)
+}
+
+async fn extract_document(
+    storage: &FileStorage,
+    table: &EmbeddableTable,
+    file_row: &FileRow,
+    document: &mut VectorDbDocument,
+) -> Result<(), EmbeddingError> {
+    let Some(path) = file_row.path.clone() else {
+        log::info!(
+            "Embedding {} {} from {}, it has no file",
+            table.name,
+            document.id,
+            table.text_column
+        );
+        document.content = query_column(table, document.id, table.text_column)
+            .await?
+            .unwrap_or_default();
+        return Ok(());
+    };
+
+    let bytes = read_file(storage, &path).await?;
+    let Some(kind) = FileKind::detect(&path, file_row.mime.as_deref(), &bytes) else {
+        log::info!(
+            "Embedding `{}` from {}.{}, its type isn't supported",
+            path,
+            table.name,
+            table.text_column
+        );
+        document.content = query_column(table, document.id, table.text_column)
+            .await?
+            .unwrap_or_default();
+        return Ok(());
+    };
+
+    // Parsing is CPU bound, and some PDFs make the parser panic
+    let extracted = tokio::task::spawn_blocking(move || extract_text(kind, &bytes))
+        .await
+        .map_err(|e| EmbeddingError::Extraction(format!("`{}`: {}", path, e)))??;
+    document.content = extracted.text;
+    document.page_offsets = extracted.page_offsets;
+
+    Ok(())
+}
+
+// The documents whose text could be filled in, and those whose file couldn't be read
+// or extracted along with why
+pub struct ExtractedDocuments {
+    pub documents: Vec<VectorDbDocument>,
+    pub failed: Vec<(VectorDbDocument, EmbeddingError)>,
+}
+
+// Fills in the text of documents without one from their row's file. A file failing
+// only fails its own document; the database failing fails them all.
+pub async fn extract_file_texts(
+    documents: Vec<VectorDbDocument>,
+) -> Result<ExtractedDocuments, EmbeddingError> {
+    let Some(storage) = file_storage() else {
+        return Ok(ExtractedDocuments {
+            documents,
+            failed: Vec::new(),
+        });
+    };
+
+    let mut ids_by_table: HashMap<&str, Vec<i64>> = HashMap::new();
+    for document in documents.iter().filter(|document| document.content.is_empty()) {
+        ids_by_table
+            .entry(document.table_name.as_str())
+            .or_default()
+            .push(document.id);
+    }
+
+    let mut file_rows = HashMap::new();
+    for (table_name, ids) in ids_by_table {
+        let Some(table) = embeddable_table(table_name) else {
+            continue;
+        };
+        let Some(file) = &table.file else {
+            continue;
+        };
+
+        for (id, file_row) in query_file_rows(table, file, &ids).await? {
+            file_rows.insert((table_name.to_string(), id), file_row);
+        }
+    }
+
+    let mut extracted = ExtractedDocuments {
+        documents: Vec::with_capacity(documents.len()),
+        failed: Vec::new(),
+    };
+    for mut document in documents {
+        let key = document_key(&document);
+        match (embeddable_table(&document.table_name), file_rows.get(&key)) {
+            (Some(table), Some(file_row)) if document.content.is_empty() => {
+                match extract_document(&storage, table, file_row, &mut document).await {
+                    Ok(()) => extracted.documents.push(document),
+                    Err(err) => extracted.failed.push((document, err)),
+                }
+            }
+            _ => extracted.documents.push(document),
+        }
+    }
+
+    Ok(extracted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn page_at_counts_pages_from_one() {
+        let page_offsets = [10, 25];
+
+        assert_eq!(page_at(&page_offsets, 0), 1);
+        assert_eq!(page_at(&page_offsets, 9), 1);
+        assert_eq!(page_at(&page_offsets, 10), 2);
+        assert_eq!(page_at(&page_offsets, 24), 2);
+        assert_eq!(page_at(&page_offsets, 25), 3);
+        assert_eq!(page_at(&[], 100), 1);
+    }
+
+    #[test]
+    fn from_pages_starts_every_page_on_a_new_line() {
+        let extracted = ExtractedText::from_pages(vec![
+            "One".to_string(),
+            "Two\n".to_string(),
+            "Three".to_string(),
+        ]);
+
+        assert_eq!(extracted.text, "One\nTwo\nThree");
+        assert_eq!(extracted.page_offsets, vec![4, 8]);
+    }
+
+    #[test]
+    fn detect_prefers_the_mime_type_then_the_extension_then_the_contents() {
+        let pdf = b"%PDF-1.7";
+
+        assert_eq!(
+            FileKind::detect("notes.txt", Some("text/html; charset=utf-8"), pdf),
+            Some(FileKind::Html)
+        );
+        assert_eq!(
+            FileKind::detect("notes.txt", Some("application/octet-stream"), pdf),
+            Some(FileKind::Text)
+        );
+        assert_eq!(FileKind::detect("upload", None, pdf), Some(FileKind::Pdf));
+        assert_eq!(
+            FileKind::detect("upload", None, b"PK\x03\x04"),
+            Some(FileKind::Docx)
+        );
+        assert_eq!(
+            FileKind::detect("upload.bin", None, b"  <!DOCTYPE html><p>Hi</p>"),
+            Some(FileKind::Html)
+        );
+        assert_eq!(FileKind::detect("upload.bin", None, b"plain bytes"), None);
+    }
+
+    #[tokio::test]
+    async fn read_file_stays_inside_the_storage() {
+        let storage = FileStorage::Local(PathBuf::from("tests/files"));
+
+        assert!(read_file(&storage, "test_pdf.pdf").await.is_ok());
+        assert!(read_file(&storage, "/test_pdf.pdf").await.is_ok());
+        for path in [
+            "../Cargo.toml",
+            "uploads/../../Cargo.toml",
+            "./test_pdf.pdf",
+        ] {
+            assert!(
+                matches!(
+                    read_file(&storage, path).await,
+                    Err(EmbeddingError::Extraction(message)) if message.contains("invalid file path")
+                ),
+                "{} was read",
+                path
+            );
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use super::ExtractedText;
+use crate::embed::errors::EmbeddingError;
+
+pub fn extract(bytes: &[u8]) -> Result<ExtractedText, EmbeddingError> {
+    let pages = pdf_extract::extract_text_from_mem_by_pages(bytes)
+        .map_err(|e| EmbeddingError::Extraction(format!("PDF: {}", e)))?;
+
+    Ok(ExtractedText::from_pages(pages))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::embed::extract::page_at;
+
+    #[test]
+    fn extract_finds_every_page_of_a_pdf() {
+        let bytes = std::fs::read("tests/files/test_pdf.pdf").unwrap();
+
+        let extracted = extract(&bytes).unwrap();
+
+        assert_eq!(extracted.page_offsets.len(), 9);
+        assert!(extracted
+            .page_offsets
+            .windows(2)
+            .all(|pair| pair[0] < pair[1]));
+        assert!(*extracted.page_offsets.last().unwrap() < extracted.text.len() as u64);
+
+        let page_of = |needle: &str| {
+            page_at(
+                &extracted.page_offsets,
+                extracted.text.find(needle).unwrap(),
+            )
+        };
+        assert_eq!(page_of("About Us"), 1);
+        assert_eq!(page_of("Audience Demographics"), 2);
+        assert_eq!(page_of("Body Sponsorship"), 6);
+        assert_eq!(page_of("reserve a sponsorship"), 10);
+    }
+
+    #[test]
+    fn extract_rejects_what_isnt_a_pdf() {
+        assert!(matches!(
+            extract(b"%PDF-1.7 but not really"),
+            Err(EmbeddingError::Extraction(_))
+        ));
+    }
+}
@@ -1,76 +1,763 @@
 use std::collections::HashMap;
-use async_openai::types::CreateEmbeddingRequestArgs;
-use qdrant_client::qdrant::{Condition, Filter, SearchParams, SearchPoints, SearchResponse};
-use crate::embed::instances::{get_embedding_client_instance, MODEL_NAME};
-use crate::grpc::server::vecembed_rpc::IdList;
+use qdrant_client::qdrant::{QuantizationSearchParams, SearchParams};
+use crate::config::{get_config, QuantizationSettings};
+use crate::embed::embedder::{embed_texts_with, format_query};
+use crate::embed::language::detect_language;
+use crate::embed::limiter::Priority;
+use crate::embed::tables::{embeddable_table, EmbeddableTable, Visibility, EMBEDDABLE_TABLES};
+use crate::grpc::server::vecembed_rpc::{DocumentRef, IdList};
 
 use super::{
-    collections::COLLECTION_NAME,
-    errors::{EmbeddingError, QdrantClientError},
-    instances::{get_qdrant_instance},
+    errors::EmbeddingError,
+    instances::get_vector_store_instance,
+    store::{Recommendation, ScoredChunk, SearchScope, VectorStore},
 };
 
+// Rescore with oversampling by default when the collection is quantized,
+// keeping whatever the request set explicitly
+fn with_quantization_defaults(
+    params: Option<SearchParams>,
+    settings: Option<QuantizationSettings>,
+) -> Option<SearchParams> {
+    let Some(settings) = settings else {
+        return params;
+    };
+
+    let mut params = params.unwrap_or_default();
+    let quantization = params.quantization.unwrap_or_default();
+    params.quantization = Some(QuantizationSearchParams {
+        ignore: quantization.ignore,
+        rescore: quantization.rescore.or(Some(settings.rescore)),
+        oversampling: quantization.oversampling.or(Some(settings.oversampling)),
+    });
+
+    Some(params)
+}
+
+// Added to the score of chunks in the preferred language when a request doesn't say
+const DEFAULT_LANGUAGE_BOOST: f32 = 0.1;
+// Candidates fetched per result when reranking by language, so boosted chunks from
+// further down the ranking can move up
+const LANGUAGE_OVERSAMPLING: u64 = 3;
+// Candidates fetched per result when collapsing near duplicates, which drops some
+const DUPLICATE_OVERSAMPLING: u64 = 2;
+// Chunks of the referenced documents recommendations are made from, at most
+const MAX_EXAMPLE_CHUNKS: u32 = 256;
+
+#[derive(Debug, Default)]
+pub struct RetrievalOptions {
+    pub limit: Option<u64>,
+    pub params: Option<SearchParams>,
+    // ISO 639-3 codes of the only languages to return; empty returns any
+    pub languages: Vec<String>,
+    // Chunks in this language rank higher; "auto" means the query's language
+    pub preferred_language: Option<String>,
+    pub language_boost: Option<f32>,
+    // Return one document of each set of near duplicates
+    pub collapse_duplicates: bool,
+}
+
+impl RetrievalOptions {
+    fn preferred_language(&self, query: &str) -> Option<String> {
+        match self.preferred_language.as_deref().map(str::trim) {
+            None | Some("") => None,
+            Some("auto") => detect_language(query).map(str::to_string),
+            Some(language) => Some(language.to_ascii_lowercase()),
+        }
+    }
+
+    fn languages(&self) -> Vec<String> {
+        self.languages
+            .iter()
+            .map(|language| language.trim().to_ascii_lowercase())
+            .filter(|language| !language.is_empty())
+            .collect()
+    }
+
+    // Chunks to fetch for `limit` results after reranking and collapsing
+    fn search_limit(&self, preferred_language: Option<&str>) -> u64 {
+        let mut search_limit = self.limit();
+        if preferred_language.is_some() {
+            search_limit *= LANGUAGE_OVERSAMPLING;
+        }
+        if self.collapse_duplicates {
+            search_limit *= DUPLICATE_OVERSAMPLING;
+        }
+        search_limit
+    }
+
+    fn limit(&self) -> u64 {
+        self.limit.unwrap_or(100)
+    }
+
+    fn rerank(&self, mut chunks: Vec<ScoredChunk>, preferred_language: Option<&str>) -> Vec<ScoredChunk> {
+        if let Some(language) = preferred_language {
+            rerank_by_language(
+                &mut chunks,
+                language,
+                self.language_boost.unwrap_or(DEFAULT_LANGUAGE_BOOST),
+            );
+        }
+        if self.collapse_duplicates {
+            chunks = collapse_duplicates(chunks);
+        }
+        chunks.truncate(self.limit() as usize);
+        chunks
+    }
+}
+
+// Moves chunks in the preferred language up by the boost
+fn rerank_by_language(chunks: &mut [ScoredChunk], preferred_language: &str, boost: f32) {
+    for chunk in chunks.iter_mut() {
+        if chunk.payload.get("language").and_then(|value| value.as_str()) == Some(preferred_language) {
+            chunk.score += boost;
+        }
+    }
+    chunks.sort_by(|a, b| b.score.total_cmp(&a.score));
+}
+
+// Keeps the chunks of only one document out of each set of near duplicates, whichever
+// ranks highest
+fn collapse_duplicates(chunks: Vec<ScoredChunk>) -> Vec<ScoredChunk> {
+    let mut kept_documents: HashMap<(String, i64), i64> = HashMap::new();
+
+    chunks
+        .into_iter()
+        .filter(|chunk| {
+            let payload_i64 = |key: &str| chunk.payload.get(key).and_then(|value| value.as_i64());
+            let table_name = chunk
+                .payload
+                .get("table_name")
+                .and_then(|value| value.as_str())
+                .unwrap_or_default()
+                .to_string();
+            let document_id = payload_i64("document_id").unwrap_or_default();
+            let original_id = payload_i64("duplicate_of").unwrap_or(document_id);
+
+            *kept_documents
+                .entry((table_name, original_id))
+                .or_insert(document_id)
+                == document_id
+        })
+        .collect()
+}
+
+// Every document of the table the user is allowed to see
+fn table_scope(table: &EmbeddableTable, user_id: i64, languages: &[String]) -> SearchScope {
+    SearchScope {
+        table_name: table.name.to_string(),
+        user_id: match table.visibility {
+            Visibility::Public => None,
+            Visibility::Owner => Some(user_id),
+        },
+        languages: languages.to_vec(),
+        ..Default::default()
+    }
+}
+
+// Collect the documents of the tables searched by default the user is allowed to see,
+// or only those of `filter_ids` when it names any
+fn search_scopes(
+    user_id: i64,
+    filter_ids: &HashMap<String, IdList>,
+    languages: &[String],
+) -> Vec<SearchScope> {
+    let mut filtered_scopes = vec![];
+    for (table_name, id_list) in filter_ids {
+        if let Some(table) = embeddable_table(table_name) {
+            if !id_list.ids.is_empty() {
+                filtered_scopes.push(SearchScope {
+                    document_ids: id_list.ids.clone(),
+                    ..table_scope(table, user_id, languages)
+                });
+            }
+        }
+    }
+
+    if !filtered_scopes.is_empty() {
+        filtered_scopes
+    } else {
+        EMBEDDABLE_TABLES
+            .iter()
+            .filter(|table| table.searched_by_default)
+            .map(|table| table_scope(table, user_id, languages))
+            .collect()
+    }
+}
+
+// Scopes of the referenced documents, limited to what the user is allowed to see
+fn reference_scopes(
+    references: &[DocumentRef],
+    user_id: i64,
+) -> Result<Vec<SearchScope>, EmbeddingError> {
+    let mut ids_by_table: HashMap<&'static str, Vec<i64>> = HashMap::new();
+    for reference in references {
+        let table = embeddable_table(&reference.table_name)
+            .ok_or_else(|| EmbeddingError::UnknownTable(reference.table_name.clone()))?;
+        ids_by_table.entry(table.name).or_default().push(reference.id);
+    }
+
+    Ok(ids_by_table
+        .into_iter()
+        .filter_map(|(table_name, document_ids)| {
+            Some(SearchScope {
+                document_ids,
+                ..table_scope(embeddable_table(table_name)?, user_id, &[])
+            })
+        })
+        .collect())
+}
+
 pub async fn get_documents(
     query: &str,
     task_description: &str,
     user_id: i64,
     filter_ids: HashMap<String, IdList>,
-    limit: Option<u64>,
-    params: Option<SearchParams>,
-) -> Result<SearchResponse, EmbeddingError> {
-    let client = get_qdrant_instance().await;
-    let embedding_client = get_embedding_client_instance().await;
+    vector_name: &str,
+    options: RetrievalOptions,
+) -> Result<Vec<ScoredChunk>, EmbeddingError> {
+    let store = get_vector_store_instance().await;
+    let embedding_config = &get_config().embedding;
+
+    // The query has to be embedded by the same model as the vector it's searched against
+    let provider = embedding_config
+        .provider(Some(vector_name))
+        .ok_or_else(|| EmbeddingError::UnknownVector(vector_name.to_string()))?;
+
+    // Truncated to the configured dimension, like the stored chunk vectors
+    let query_embedding = embed_texts_with(
+        &provider,
+        vec![format_query(&provider, task_description, query)],
+        Priority::Query,
+    )
+    .await?
+    .remove(0);
+
+    let scopes = search_scopes(user_id, &filter_ids, &options.languages());
+    let preferred_language = options.preferred_language(query);
+
+    let chunks = store
+        .search(
+            get_config().read_collection_name(),
+            embedding_config.stored_vector_name(&provider).as_deref(),
+            query_embedding,
+            scopes,
+            options.search_limit(preferred_language.as_deref()),
+            with_quantization_defaults(
+                options.params,
+                get_config().active_collection().quantization,
+            ),
+        )
+        .await?;
+
+    Ok(options.rerank(chunks, preferred_language.as_deref()))
+}
+
+// Finds chunks of other documents like the positive ones and unlike the negative ones,
+// out of those the user is allowed to see. The referenced documents themselves are
+// never returned, and ones the user can't see are ignored.
+pub async fn recommend_documents(
+    positive: &[DocumentRef],
+    negative: &[DocumentRef],
+    user_id: i64,
+    filter_ids: HashMap<String, IdList>,
+    vector_name: &str,
+    options: RetrievalOptions,
+) -> Result<Vec<ScoredChunk>, EmbeddingError> {
+    let store = get_vector_store_instance().await;
+    recommend_documents_in(
+        store,
+        positive,
+        negative,
+        user_id,
+        filter_ids,
+        vector_name,
+        options,
+    )
+    .await
+}
+
+async fn recommend_documents_in(
+    store: &dyn VectorStore,
+    positive: &[DocumentRef],
+    negative: &[DocumentRef],
+    user_id: i64,
+    filter_ids: HashMap<String, IdList>,
+    vector_name: &str,
+    options: RetrievalOptions,
+) -> Result<Vec<ScoredChunk>, EmbeddingError> {
+    let embedding_config = &get_config().embedding;
+    let collection = get_config().read_collection_name();
 
+    let provider = embedding_config
+        .provider(Some(vector_name))
+        .ok_or_else(|| EmbeddingError::UnknownVector(vector_name.to_string()))?;
 
-    let request = CreateEmbeddingRequestArgs::default()
-        .model(MODEL_NAME)
-        .input(vec![("Instruct: ").to_owned() + task_description + "\nQuery: " + query])
-        .build()?;
+    let positive_scopes = reference_scopes(positive, user_id)?;
+    let negative_scopes = reference_scopes(negative, user_id)?;
+    let positive_ids = store
+        .chunk_ids(collection, positive_scopes.clone(), MAX_EXAMPLE_CHUNKS)
+        .await?;
+    if positive_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+    let negative_ids = if negative_scopes.is_empty() {
+        Vec::new()
+    } else {
+        store
+            .chunk_ids(collection, negative_scopes.clone(), MAX_EXAMPLE_CHUNKS)
+            .await?
+    };
 
-    let response = embedding_client.embeddings().create(request).await?;
+    // Every chunk of the referenced documents, whoever can see them
+    let excluded = positive_scopes
+        .into_iter()
+        .chain(negative_scopes)
+        .map(|scope| SearchScope {
+            table_name: scope.table_name,
+            document_ids: scope.document_ids,
+            ..Default::default()
+        })
+        .collect();
 
-    // Collect all relevant external contents and user's uploaded files
-    let mut content_conditions = vec![Condition::matches("table_name", "contents".to_string())];
-    let mut uploaded_file_conditions = vec![
-        Condition::matches("table_name", "uploaded_files".to_string()),
-        Condition::matches("user_id", user_id),
-    ];
+    // There's no query to tell a language by, so "auto" doesn't prefer any
+    let preferred_language = options.preferred_language("");
+    let chunks = store
+        .recommend(
+            collection,
+            embedding_config.stored_vector_name(&provider).as_deref(),
+            Recommendation {
+                positive: positive_ids,
+                negative: negative_ids,
+                excluded,
+            },
+            search_scopes(user_id, &filter_ids, &options.languages()),
+            options.search_limit(preferred_language.as_deref()),
+            with_quantization_defaults(
+                options.params,
+                get_config().active_collection().quantization,
+            ),
+        )
+        .await?;
 
-    let mut should_filters = vec![];
-    for (table_name, id_list) in &filter_ids {
-        if table_name == "contents" && !id_list.ids.is_empty() {
-            content_conditions.push(Condition::matches("id", id_list.ids.clone().into_iter().collect::<Vec<i64>>()));
-            should_filters.push(Filter::must(content_conditions.clone()).into());
+    Ok(options.rerank(chunks, preferred_language.as_deref()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::config::{CollectionConfig, QuantizationKind};
+    use crate::embed::document_text::DocumentKey;
+    use crate::embed::store::{VectorPoint, VectorSpec};
+
+    // Answers the calls recommendations make, recording what reached it
+    #[derive(Default)]
+    struct RecordingStore {
+        chunk_id_scopes: Mutex<Vec<Vec<SearchScope>>>,
+        recommended: Mutex<Vec<(Recommendation, Vec<SearchScope>)>>,
+    }
+
+    // Recommendations have no business with the rest of the store
+    fn unanswered(call: &str) -> EmbeddingError {
+        EmbeddingError::BackendUnavailable(format!("recording store doesn't answer {}", call))
+    }
+
+    #[tonic::async_trait]
+    impl VectorStore for RecordingStore {
+        async fn collection_exists(&self, _: &str) -> Result<bool, EmbeddingError> {
+            Err(unanswered("collection_exists"))
+        }
+
+        async fn list_collections(&self) -> Result<Vec<String>, EmbeddingError> {
+            Err(unanswered("list_collections"))
         }
-        if table_name == "uploaded_files" && !id_list.ids.is_empty() {
-            uploaded_file_conditions.push(Condition::matches("id", id_list.ids.clone().into_iter().collect::<Vec<i64>>()));
-            should_filters.push(Filter::must(uploaded_file_conditions.clone()).into());
 
+        async fn resolve_alias(&self, _: &str) -> Result<Option<String>, EmbeddingError> {
+            Err(unanswered("resolve_alias"))
+        }
+
+        async fn switch_alias(&self, _: &str, _: &str) -> Result<(), EmbeddingError> {
+            Err(unanswered("switch_alias"))
+        }
+
+        async fn ensure_collection(
+            &self,
+            _: &CollectionConfig,
+            _: &[VectorSpec],
+        ) -> Result<Vec<VectorSpec>, EmbeddingError> {
+            Err(unanswered("ensure_collection"))
+        }
+
+        async fn sync_collection(&self, _: &CollectionConfig) -> Result<(), EmbeddingError> {
+            Err(unanswered("sync_collection"))
+        }
+
+        async fn upsert_points(&self, _: &str, _: Vec<VectorPoint>) -> Result<(), EmbeddingError> {
+            Err(unanswered("upsert_points"))
+        }
+
+        async fn delete_documents(&self, _: &str, _: &[DocumentKey]) -> Result<(), EmbeddingError> {
+            Err(unanswered("delete_documents"))
+        }
+
+        async fn search(
+            &self,
+            _: &str,
+            _: Option<&str>,
+            _: Vec<f32>,
+            _: Vec<SearchScope>,
+            _: u64,
+            _: Option<SearchParams>,
+        ) -> Result<Vec<ScoredChunk>, EmbeddingError> {
+            Err(unanswered("search"))
+        }
+
+        // One chunk per document, named after it
+        async fn chunk_ids(
+            &self,
+            _: &str,
+            scopes: Vec<SearchScope>,
+            _: u32,
+        ) -> Result<Vec<String>, EmbeddingError> {
+            let ids = scopes
+                .iter()
+                .flat_map(|scope| {
+                    scope
+                        .document_ids
+                        .iter()
+                        .map(move |id| format!("{}:{}", scope.table_name, id))
+                })
+                .collect();
+            self.chunk_id_scopes.lock().unwrap().push(scopes);
+            Ok(ids)
+        }
+
+        async fn recommend(
+            &self,
+            _: &str,
+            _: Option<&str>,
+            recommendation: Recommendation,
+            scopes: Vec<SearchScope>,
+            _: u64,
+            _: Option<SearchParams>,
+        ) -> Result<Vec<ScoredChunk>, EmbeddingError> {
+            self.recommended
+                .lock()
+                .unwrap()
+                .push((recommendation, scopes));
+            Ok(Vec::new())
         }
     }
 
-    let filter = if !should_filters.is_empty() {
-        Some(Filter::should(should_filters))
-    } else {
-        Some(Filter::should([
-            Filter::must(content_conditions).into(),
-            Filter::must(uploaded_file_conditions).into(),
-        ]))
-    };
+    fn reference(table_name: &str, id: i64) -> DocumentRef {
+        DocumentRef {
+            table_name: table_name.to_string(),
+            id,
+        }
+    }
 
-    Ok(client
-        .search_points(
-            SearchPoints {
-                collection_name: COLLECTION_NAME.to_string(),
-                vector: response.data[0].embedding.clone(),
-                limit: limit.unwrap_or(100),
-                with_payload: Some(vec!["document_id", "start", "end", "table_name"].into()),
-                with_vectors: Some(false.into()),
-                filter,
-                params,
-                ..Default::default()
-            }
+    // Table, user, ids and languages of a scope
+    type DescribedScope = (String, Option<i64>, Vec<i64>, Vec<String>);
+
+    // Scopes in a stable order
+    fn described(scopes: &[SearchScope]) -> Vec<DescribedScope> {
+        let mut described: Vec<_> = scopes
+            .iter()
+            .map(|scope| {
+                let mut ids = scope.document_ids.clone();
+                ids.sort();
+                (
+                    scope.table_name.clone(),
+                    scope.user_id,
+                    ids,
+                    scope.languages.clone(),
+                )
+            })
+            .collect();
+        described.sort();
+        described
+    }
+
+    fn sorted(mut ids: Vec<String>) -> Vec<String> {
+        ids.sort();
+        ids
+    }
+
+    async fn recommend(
+        store: &RecordingStore,
+        positive: &[DocumentRef],
+        negative: &[DocumentRef],
+        filter_ids: HashMap<String, IdList>,
+        options: RetrievalOptions,
+    ) -> Result<Vec<ScoredChunk>, EmbeddingError> {
+        recommend_documents_in(store, positive, negative, 7, filter_ids, "", options).await
+    }
+
+    #[tokio::test]
+    async fn recommendations_are_made_from_the_references_and_exclude_them() {
+        let store = RecordingStore::default();
+        let positive = [
+            reference("contents", 1),
+            reference("uploaded_files", 2),
+            reference("contents", 3),
+        ];
+        let negative = [reference("chat_messages", 4)];
+
+        recommend(
+            &store,
+            &positive,
+            &negative,
+            HashMap::new(),
+            RetrievalOptions::default(),
         )
         .await
-        .map_err(QdrantClientError::from)?)
+        .unwrap();
+
+        // References are looked up as the user sees them
+        let chunk_id_scopes = store.chunk_id_scopes.lock().unwrap();
+        assert_eq!(
+            described(&chunk_id_scopes[0]),
+            vec![
+                ("contents".to_string(), None, vec![1, 3], vec![]),
+                ("uploaded_files".to_string(), Some(7), vec![2], vec![]),
+            ]
+        );
+        assert_eq!(
+            described(&chunk_id_scopes[1]),
+            vec![("chat_messages".to_string(), Some(7), vec![4], vec![])]
+        );
+
+        let recommended = store.recommended.lock().unwrap();
+        let (recommendation, _) = &recommended[0];
+        assert_eq!(
+            sorted(recommendation.positive.clone()),
+            ["contents:1", "contents:3", "uploaded_files:2"]
+        );
+        assert_eq!(recommendation.negative, ["chat_messages:4"]);
+        assert_eq!(
+            described(&recommendation.excluded),
+            vec![
+                ("chat_messages".to_string(), None, vec![4], vec![]),
+                ("contents".to_string(), None, vec![1, 3], vec![]),
+                ("uploaded_files".to_string(), None, vec![2], vec![]),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn recommendations_search_the_filtered_tables_as_the_user() {
+        let store = RecordingStore::default();
+        let filter_ids = HashMap::from([
+            ("chat_messages".to_string(), IdList { ids: vec![5, 6] }),
+            ("contents".to_string(), IdList { ids: vec![] }),
+        ]);
+        let options = RetrievalOptions {
+            languages: vec![" ENG ".to_string()],
+            ..Default::default()
+        };
+
+        recommend(
+            &store,
+            &[reference("contents", 1)],
+            &[],
+            filter_ids,
+            options,
+        )
+        .await
+        .unwrap();
+
+        let recommended = store.recommended.lock().unwrap();
+        let (recommendation, scopes) = &recommended[0];
+        assert!(recommendation.negative.is_empty());
+        assert_eq!(
+            described(scopes),
+            vec![(
+                "chat_messages".to_string(),
+                Some(7),
+                vec![5, 6],
+                vec!["eng".to_string()]
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn recommendations_without_filters_search_the_default_tables() {
+        let store = RecordingStore::default();
+
+        recommend(
+            &store,
+            &[reference("contents", 1)],
+            &[],
+            HashMap::new(),
+            RetrievalOptions::default(),
+        )
+        .await
+        .unwrap();
+
+        let recommended = store.recommended.lock().unwrap();
+        let expected: Vec<_> = EMBEDDABLE_TABLES
+            .iter()
+            .filter(|table| table.searched_by_default)
+            .map(|table| table_scope(table, 7, &[]))
+            .collect();
+        assert_eq!(described(&recommended[0].1), described(&expected));
+    }
+
+    #[tokio::test]
+    async fn recommendations_need_chunks_of_a_positive_reference() {
+        let store = RecordingStore::default();
+
+        let chunks = recommend(
+            &store,
+            &[],
+            &[reference("contents", 4)],
+            HashMap::new(),
+            RetrievalOptions::default(),
+        )
+        .await
+        .unwrap();
+
+        assert!(chunks.is_empty());
+        assert!(store.recommended.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn recommendations_reject_unknown_tables() {
+        let store = RecordingStore::default();
+
+        let err = recommend(
+            &store,
+            &[reference("contents", 1)],
+            &[reference("passwords", 2)],
+            HashMap::new(),
+            RetrievalOptions::default(),
+        )
+        .await
+        .unwrap_err();
+
+        assert!(matches!(err, EmbeddingError::UnknownTable(table) if table == "passwords"));
+        assert!(store.chunk_id_scopes.lock().unwrap().is_empty());
+    }
+
+    fn quantization(rescore: bool, oversampling: f64) -> QuantizationSettings {
+        QuantizationSettings {
+            kind: QuantizationKind::Scalar,
+            quantile: None,
+            always_ram: None,
+            rescore,
+            oversampling,
+        }
+    }
+
+    #[test]
+    fn quantization_defaults_apply_to_requests_without_params() {
+        let params = with_quantization_defaults(None, Some(quantization(true, 2.0))).unwrap();
+
+        assert_eq!(
+            params.quantization,
+            Some(QuantizationSearchParams {
+                ignore: None,
+                rescore: Some(true),
+                oversampling: Some(2.0),
+            })
+        );
+    }
+
+    #[test]
+    fn quantization_defaults_keep_what_the_request_set() {
+        let requested = SearchParams {
+            hnsw_ef: Some(128),
+            quantization: Some(QuantizationSearchParams {
+                ignore: Some(false),
+                rescore: Some(false),
+                oversampling: None,
+            }),
+            ..Default::default()
+        };
+
+        let params =
+            with_quantization_defaults(Some(requested), Some(quantization(true, 2.0))).unwrap();
+
+        assert_eq!(params.hnsw_ef, Some(128));
+        assert_eq!(
+            params.quantization,
+            Some(QuantizationSearchParams {
+                ignore: Some(false),
+                rescore: Some(false),
+                oversampling: Some(2.0),
+            })
+        );
+    }
+
+    #[test]
+    fn quantization_defaults_need_a_quantized_collection() {
+        assert_eq!(with_quantization_defaults(None, None), None);
+
+        let requested = SearchParams {
+            exact: Some(true),
+            ..Default::default()
+        };
+        assert_eq!(with_quantization_defaults(Some(requested), None), Some(requested));
+    }
+
+    fn chunk(score: f32, payload: serde_json::Value) -> ScoredChunk {
+        ScoredChunk {
+            score,
+            payload: serde_json::from_value(payload).unwrap(),
+        }
+    }
+
+    fn scores(chunks: &[ScoredChunk]) -> Vec<f32> {
+        chunks.iter().map(|chunk| chunk.score).collect()
+    }
+
+    #[test]
+    fn rerank_by_language_moves_preferred_chunks_up() {
+        let mut chunks = vec![
+            chunk(0.9, serde_json::json!({ "language": "deu" })),
+            chunk(0.8, serde_json::json!({ "language": "eng" })),
+            chunk(0.5, serde_json::json!({ "language": "eng" })),
+        ];
+
+        rerank_by_language(&mut chunks, "eng", 0.25);
+
+        assert_eq!(scores(&chunks), vec![1.05, 0.9, 0.75]);
+    }
+
+    #[test]
+    fn rerank_by_language_leaves_chunks_without_a_language_alone() {
+        let mut chunks = vec![
+            chunk(0.7, serde_json::json!({})),
+            chunk(0.6, serde_json::json!({ "language": "fra" })),
+        ];
+
+        rerank_by_language(&mut chunks, "eng", 0.25);
+
+        assert_eq!(scores(&chunks), vec![0.7, 0.6]);
+    }
+
+    #[test]
+    fn collapse_duplicates_keeps_the_highest_ranked_document_of_each_set() {
+        let chunks = vec![
+            chunk(0.9, serde_json::json!({ "table_name": "contents", "document_id": 2, "duplicate_of": 1 })),
+            chunk(0.8, serde_json::json!({ "table_name": "contents", "document_id": 1 })),
+            chunk(0.7, serde_json::json!({ "table_name": "contents", "document_id": 2, "duplicate_of": 1 })),
+            chunk(0.6, serde_json::json!({ "table_name": "contents", "document_id": 3, "duplicate_of": 1 })),
+            chunk(0.5, serde_json::json!({ "table_name": "contents", "document_id": 4 })),
+        ];
+
+        assert_eq!(scores(&collapse_duplicates(chunks)), vec![0.9, 0.7, 0.5]);
+    }
+
+    #[test]
+    fn collapse_duplicates_tells_tables_apart() {
+        let chunks = vec![
+            chunk(0.9, serde_json::json!({ "table_name": "contents", "document_id": 1 })),
+            chunk(0.8, serde_json::json!({ "table_name": "uploaded_files", "document_id": 2, "duplicate_of": 1 })),
+        ];
+
+        assert_eq!(scores(&collapse_duplicates(chunks)), vec![0.9, 0.8]);
+    }
 }
@@ -1,36 +1,29 @@
 use super::{
-    collections::EmbeddableEntityColumn, create::create_and_save_embeddings,
-    errors::EmbeddingError, instances::get_db_instance,
+    checkpoint::{
+        complete_checkpoint, reset_checkpoint, save_checkpoint, unfinished_checkpoint, ImportCheckpoint,
+    },
+    create::{
+        create_and_save_embeddings, create_and_save_embeddings_into,
+        create_and_save_streamed_embeddings, live_target, IngestTarget,
+    },
+    errors::EmbeddingError,
+    estimate::ImportEstimate,
+    extract::file_storage_configured,
+    instances::get_db_instance,
+    sync_state::{stale_rows_filter, sync_state_join, SYNC_STATE},
+    tables::{embeddable_table, embeddable_table_names, EmbeddableTable},
 };
 
+use chrono::{DateTime, Utc};
 use log::info;
-use sea_orm::sea_query::Expr;
-use sea_orm::{
-    ColumnTrait, DatabaseConnection, EntityTrait, PaginatorTrait, QueryFilter, QueryOrder,
-    QuerySelect, QueryTrait, Select,
-};
+use sea_orm::{ConnectionTrait, DatabaseBackend, DatabaseConnection, Statement, Value};
 use thiserror::Error;
 
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
+use futures::future::try_join_all;
+use tokio::sync::Mutex;
 
 use crate::grpc::server::vecembed_rpc::VectorDbDocument;
 
-fn string_to_i64(s: &str) -> i64 {
-    match s.parse::<i64>() {
-        Ok(num) => num, // If it's a number, return it directly
-        Err(_) => {
-            // If it's not a number, hash the string
-            let mut hasher = DefaultHasher::new();
-            s.hash(&mut hasher);
-            let hash_result = hasher.finish() as i64;
-
-            // Safely convert the u64 hash to i64
-            hash_result % i64::MAX
-        }
-    }
-}
-
 #[derive(Error, Debug)]
 pub enum ImportEmbeddingsError {
     #[error(transparent)]
@@ -39,121 +32,687 @@ pub enum ImportEmbeddingsError {
     #[error(transparent)]
     EmbeddingError(#[from] EmbeddingError),
 
-    #[error("Unknown combination: {0}")]
-    UnknownCombination(String),
+    #[error("Unknown table: {0}")]
+    UnknownTable(String),
+
+    #[error("No unfinished import of {0} to resume")]
+    NoCheckpoint(String),
+
+    #[error("Reindex failed: {0}")]
+    Reindex(String),
+}
+
+// Which rows of a table an import embeds
+pub enum ImportScope {
+    // Rows whose embeddings are missing or out of date
+    Stale,
+    // Every row, e.g. when building a new collection
+    All,
+    // Stale rows plus rows synced since the given time
+    ChangedSince(DateTime<Utc>),
+}
+
+// What an import does with the checkpoint an earlier import of the table left behind
+pub enum CheckpointMode {
+    // Resume an unfinished import if there's one
+    Auto,
+    // Resume an unfinished import, failing if there's none
+    Resume,
+    // Start over, forgetting any unfinished import
+    Reset,
+    // Neither read nor write checkpoints, e.g. for a reindex
+    Off,
+}
+
+pub struct ImportOptions {
+    pub start_from: Option<u64>,
+    pub scope: ImportScope,
+    // Collection to write into instead of the live one
+    pub target: Option<IngestTarget>,
+    pub checkpoint: CheckpointMode,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        ImportOptions {
+            start_from: None,
+            scope: ImportScope::Stale,
+            target: None,
+            checkpoint: CheckpointMode::Auto,
+        }
+    }
+}
+
+async fn save_documents(
+    documents: Vec<VectorDbDocument>,
+    read_at: DateTime<Utc>,
+    options: &ImportOptions,
+    progress: &Mutex<ImportProgress>,
+) -> Result<(), EmbeddingError> {
+    // Dry runs only chunk the documents, without embedding or storing them
+    if progress.lock().await.dry_run {
+        let estimate = ImportEstimate::of_documents(&documents).await?;
+        progress.lock().await.estimate.add(&estimate);
+        return Ok(());
+    }
+
+    match &options.target {
+        Some(target) => create_and_save_embeddings_into(documents, read_at, target).await?,
+        None => create_and_save_embeddings(documents, read_at).await?,
+    };
+
+    Ok(())
+}
+
+// Like save_documents, for a document whose text is read from the table as it's embedded
+async fn save_streamed_document(
+    document: VectorDbDocument,
+    table: &EmbeddableTable,
+    read_at: DateTime<Utc>,
+    options: &ImportOptions,
+    progress: &Mutex<ImportProgress>,
+) -> Result<(), EmbeddingError> {
+    let db = get_db_instance().await;
+    let id = document.id as u64;
+    // SUBSTRING counts characters from 1
+    let read_window = |position: usize| async move {
+        Ok(fetch_text_chunk(db, table, id, position + 1).await?)
+    };
+
+    if progress.lock().await.dry_run {
+        let estimate =
+            ImportEstimate::of_streamed_document(document, MAX_TEXT_CHUNK_SIZE, read_window).await?;
+        progress.lock().await.estimate.add(&estimate);
+        return Ok(());
+    }
+
+    match &options.target {
+        Some(target) => {
+            create_and_save_streamed_embeddings(
+                document,
+                MAX_TEXT_CHUNK_SIZE,
+                read_window,
+                read_at,
+                target,
+            )
+            .await
+        }
+        None => {
+            let target = live_target().await?;
+            create_and_save_streamed_embeddings(
+                document,
+                MAX_TEXT_CHUNK_SIZE,
+                read_window,
+                read_at,
+                &target,
+            )
+            .await
+        }
+    }
 }
 
 pub const IMPORT_PAGE_SIZE: u64 = 100;
-// 1 MB maximum chunk length
-const MAX_TEXT_CHUNK_SIZE: usize = 1 * 1024 * 1024;
+const MAX_IMPORT_WORKERS: usize = 4;
+// Characters of a long text read at a time
+const MAX_TEXT_CHUNK_SIZE: usize = 1024 * 1024;
+// Texts up to this length come with the page of rows instead of a query each
+const INLINE_TEXT_LENGTH: usize = 64 * 1024;
+// HTML is cleaned whole, so it's only read a window at a time past MAX_TEXT_CHUNK_SIZE
+fn inline_text_length(table: &EmbeddableTable) -> usize {
+    if table.clean_html {
+        MAX_TEXT_CHUNK_SIZE
+    } else {
+        INLINE_TEXT_LENGTH
+    }
+}
+
 // 8 GB maximum mem limit
 const MEM_LIMIT: usize = 8 * 1024 * 1024 * 1024;
 
-pub async fn import_embeddings<E, C>(start_from: Option<u64>) -> Result<(), ImportEmbeddingsError>
-where
-    E: EmbeddableEntityColumn<E, C> + EntityTrait + Default + Send + Sync,
-    C: ColumnTrait + Send + Sync,
-    Select<E>: PaginatorTrait<'static, DatabaseConnection>,
-{
-    let db = get_db_instance().await;
-    let default_entity = E::default();
-    let entity_table_name = default_entity.table_name();
-    let mut pages = E::find()
-        .select_only()
-        .column(E::primary_key_column())
-        .apply_if(E::user_id_column(), |query, user_id_column| {
-            query.column(user_id_column)
-        })
-        .apply_if(start_from, |query, start| {
-            query.filter(E::primary_key_column().gte(start))
-        })
-        .filter(Expr::cust(
-            "(qdrant_sync_at <> updated_at OR qdrant_sync_at IS NULL OR updated_at IS NULL)",
+async fn fetch_text_chunk(
+    db: &DatabaseConnection,
+    table: &EmbeddableTable,
+    id: u64,
+    start_pos: usize,
+) -> Result<Option<String>, sea_orm::DbErr> {
+    let row = db
+        .query_one(Statement::from_sql_and_values(
+            DatabaseBackend::MySql,
+            format!(
+                "SELECT SUBSTRING({}, ?, ?) AS content_chunk FROM {} WHERE {} = ?",
+                table.text_column, table.name, table.id_column
+            ),
+            [
+                (start_pos as u64).into(),
+                (MAX_TEXT_CHUNK_SIZE as u64).into(),
+                id.into(),
+            ],
         ))
-        .order_by_asc(E::order_by_column())
-        .into_json()
-        .paginate(db, IMPORT_PAGE_SIZE);
+        .await?;
+
+    match row {
+        Some(row) => row.try_get::<Option<String>>("", "content_chunk"),
+        None => Ok(None),
+    }
+}
+
+// The rows an import selects, as a WHERE clause and its values
+struct RowFilter {
+    clause: String,
+    values: Vec<Value>,
+}
+
+// Progress of the workers of one import, each walking its own range of ids
+struct ImportProgress {
+    table_name: &'static str,
+    // Every row up to here was done before the import started
+    resumed_from: Option<u64>,
+    ranges: Vec<RangeProgress>,
+    processed_rows: u64,
+    total_rows: u64,
+    started_at: DateTime<Utc>,
+    checkpoints: bool,
+    dry_run: bool,
+    estimate: ImportEstimate,
+}
+
+struct RangeProgress {
+    end: u64,
+    last_id: Option<u64>,
+    done: bool,
+}
+
+impl ImportProgress {
+    // The highest id every row up to is done, which is where a restart resumes after.
+    // Later ranges may be further along; their done rows are no longer stale.
+    fn done_through(&self) -> Option<u64> {
+        let mut done_through = self.resumed_from;
+        for range in &self.ranges {
+            if !range.done {
+                return range.last_id.or(done_through);
+            }
+            done_through = Some(range.end);
+        }
+
+        done_through
+    }
+
+    async fn save(&self) -> Result<(), EmbeddingError> {
+        let Some(last_id) = self.done_through() else {
+            return Ok(());
+        };
+        if !self.checkpoints {
+            return Ok(());
+        }
+
+        save_checkpoint(&ImportCheckpoint {
+            table_name: self.table_name.to_string(),
+            last_id: last_id.to_string(),
+            processed_rows: self.processed_rows,
+            total_rows: self.total_rows,
+            started_at: self.started_at,
+        })
+        .await
+    }
+}
+
+// Splits the ids from `first` to `last` into up to `workers` ranges of equal width
+fn split_id_range(first: u64, last: u64, workers: usize) -> Vec<(u64, u64)> {
+    let width = (last - first) / workers as u64 + 1;
+    let mut ranges = Vec::new();
+    let mut start = first;
+    while start <= last {
+        let end = start.saturating_add(width - 1).min(last);
+        ranges.push((start, end));
+        if end == last {
+            break;
+        }
+        start = end + 1;
+    }
+
+    ranges
+}
+
+// Rows of the table an import selects: live ones in its scope, after where it resumes
+fn row_filter(
+    table: &EmbeddableTable,
+    options: &ImportOptions,
+    resumed_from: Option<u64>,
+) -> RowFilter {
+    let mut conditions = Vec::new();
+    let mut values: Vec<Value> = Vec::new();
+    if let Some(deleted_at) = table.deleted_at_column {
+        conditions.push(format!("{}.{} IS NULL", table.name, deleted_at));
+    }
+    if let Some(start) = options.start_from {
+        conditions.push(format!("{}.{} >= ?", table.name, table.id_column));
+        values.push(start.into());
+    }
+    if let Some(resumed_from) = resumed_from {
+        conditions.push(format!("{}.{} > ?", table.name, table.id_column));
+        values.push(resumed_from.into());
+    }
+    match options.scope {
+        ImportScope::Stale => {
+            let (stale, stale_values) = stale_rows_filter(table);
+            conditions.push(stale);
+            values.extend(stale_values);
+        }
+        ImportScope::All => {}
+        ImportScope::ChangedSince(since) => {
+            let (stale, stale_values) = stale_rows_filter(table);
+            conditions.push(format!("({} OR {}.synced_at >= ?)", stale, SYNC_STATE));
+            values.extend(stale_values);
+            values.push(since.naive_utc().into());
+        }
+    }
+
+    RowFilter {
+        clause: if conditions.is_empty() {
+            "TRUE".to_string()
+        } else {
+            conditions.join(" AND ")
+        },
+        values,
+    }
+}
+
+pub async fn import_embeddings(
+    table: &EmbeddableTable,
+    options: &ImportOptions,
+) -> Result<(), ImportEmbeddingsError> {
+    run_import(table, options, false).await?;
+
+    Ok(())
+}
 
-    while let Some(items) = pages.fetch_and_next().await? {
-        let primary_key_column = E::primary_key_column().to_string();
+// Selects and chunks the rows an import would, without embedding them, writing to the
+// vector store or touching checkpoints and sync state
+pub async fn estimate_import(
+    table: &EmbeddableTable,
+    options: &ImportOptions,
+) -> Result<ImportEstimate, ImportEmbeddingsError> {
+    run_import(table, options, true).await
+}
+
+async fn run_import(
+    table: &EmbeddableTable,
+    options: &ImportOptions,
+    dry_run: bool,
+) -> Result<ImportEstimate, ImportEmbeddingsError> {
+    let db = get_db_instance().await;
 
+    let checkpoint_mode = if dry_run {
+        &CheckpointMode::Off
+    } else {
+        &options.checkpoint
+    };
+    let resumed = match checkpoint_mode {
+        CheckpointMode::Auto => unfinished_checkpoint(table.name).await?,
+        CheckpointMode::Resume => Some(
+            unfinished_checkpoint(table.name)
+                .await?
+                .ok_or_else(|| ImportEmbeddingsError::NoCheckpoint(table.name.to_string()))?,
+        ),
+        CheckpointMode::Reset => {
+            reset_checkpoint(table.name).await?;
+            None
+        }
+        CheckpointMode::Off => None,
+    };
+    if let Some(checkpoint) = &resumed {
         info!(
-            "Conducting import for {} starting from ID: {}",
-            primary_key_column, items[0]["id"]
+            "Resuming import of {} after ID {} ({}/{} rows done)",
+            table.name, checkpoint.last_id, checkpoint.processed_rows, checkpoint.total_rows
         );
+    }
+    let resumed_from = resumed
+        .as_ref()
+        .and_then(|checkpoint| checkpoint.last_id.parse::<u64>().ok());
+
+    let filter = row_filter(table, options, resumed_from);
+
+    let bounds = db
+        .query_one(Statement::from_sql_and_values(
+            DatabaseBackend::MySql,
+            format!(
+                "SELECT COUNT(*) AS count, CAST(MIN({table}.{id}) AS UNSIGNED) AS first_id,
+                CAST(MAX({table}.{id}) AS UNSIGNED) AS last_id FROM {table} {join} WHERE {conditions}",
+                table = table.name,
+                id = table.id_column,
+                join = sync_state_join(table),
+                conditions = filter.clause
+            ),
+            filter.values.clone(),
+        ))
+        .await?;
+    let (remaining_rows, first_id, last_id) = match bounds {
+        Some(row) => (
+            row.try_get::<i64>("", "count")? as u64,
+            row.try_get::<Option<u64>>("", "first_id")?,
+            row.try_get::<Option<u64>>("", "last_id")?,
+        ),
+        None => (0, None, None),
+    };
+
+    let processed_rows = resumed.as_ref().map_or(0, |checkpoint| checkpoint.processed_rows);
+    let started_at = resumed
+        .as_ref()
+        .map_or_else(Utc::now, |checkpoint| checkpoint.started_at);
+
+    let workers = std::env::var("MAX_IMPORT_WORKERS")
+        .ok()
+        .and_then(|s| s.parse::<usize>().ok())
+        .unwrap_or(MAX_IMPORT_WORKERS)
+        .min(remaining_rows.div_ceil(IMPORT_PAGE_SIZE) as usize)
+        .max(1);
+    let ranges = match (first_id, last_id) {
+        (Some(first_id), Some(last_id)) => split_id_range(first_id, last_id, workers),
+        _ => Vec::new(),
+    };
+
+    let progress = Mutex::new(ImportProgress {
+        table_name: table.name,
+        resumed_from,
+        ranges: ranges
+            .iter()
+            .map(|(_, end)| RangeProgress {
+                end: *end,
+                last_id: None,
+                done: false,
+            })
+            .collect(),
+        processed_rows,
+        total_rows: processed_rows + remaining_rows,
+        started_at,
+        checkpoints: !matches!(checkpoint_mode, CheckpointMode::Off),
+        dry_run,
+        estimate: ImportEstimate::default(),
+    });
+
+    info!(
+        "Importing {} rows of {} with {} workers",
+        remaining_rows,
+        table.name,
+        ranges.len()
+    );
+
+    // The embedding limiter is shared, so the workers together never push the model
+    // server harder than a single import would
+    try_join_all(ranges.iter().enumerate().map(|(worker, range)| {
+        import_range(table, options, &filter, worker, *range, ranges.len(), &progress)
+    }))
+    .await?;
+
+    let progress = progress.into_inner();
+    if dry_run {
+        info!("Dry run of {}: {}", table.name, progress.estimate);
+        return Ok(progress.estimate);
+    }
+
+    if progress.checkpoints {
+        complete_checkpoint(table.name).await?;
+    }
+    info!("Finished import for {}: {} rows", table.name, progress.processed_rows);
+
+    Ok(progress.estimate)
+}
+
+// One worker of an import, walking the rows with ids in `range` a page at a time
+async fn import_range(
+    table: &EmbeddableTable,
+    options: &ImportOptions,
+    filter: &RowFilter,
+    worker: usize,
+    (first_id, last_id): (u64, u64),
+    workers: usize,
+    progress: &Mutex<ImportProgress>,
+) -> Result<(), ImportEmbeddingsError> {
+    let db = get_db_instance().await;
+
+    let user_id_select = table
+        .owner_column
+        .map(|column| format!("CAST({}.{} AS SIGNED)", table.name, column))
+        .unwrap_or("NULL".to_string());
+
+    let mem_limit = std::env::var("MEM_LIMIT_MB")
+        .ok()
+        .and_then(|s| s.parse::<usize>().ok())
+        .map(|mb| mb * 1024 * 1024)
+        .unwrap_or(MEM_LIMIT)
+        / workers;
+
+    // Keyset pagination: rows stop matching the stale filter once they're embedded,
+    // so an OFFSET would skip over rows that are still waiting
+    let mut after_id: Option<u64> = None;
+    loop {
+        let mut page_values = filter.values.clone();
+        let lower_bound = match after_id {
+            Some(after_id) => {
+                page_values.push(after_id.into());
+                ">"
+            }
+            None => {
+                page_values.push(first_id.into());
+                ">="
+            }
+        };
+        page_values.push(last_id.into());
+
+        // Taken before the rows are read, so edits that land while they're embedded
+        // leave them stale
+        let read_at = Utc::now();
+        let items = db
+            .query_all(Statement::from_sql_and_values(
+                DatabaseBackend::MySql,
+                format!(
+                    "SELECT CAST({table}.{id} AS UNSIGNED) AS id, {user_id} AS user_id,
+                    CHAR_LENGTH({table}.{text}) AS text_length,
+                    IF(CHAR_LENGTH({table}.{text}) <= {inline}, {table}.{text}, NULL) AS content
+                    FROM {table} {join}
+                    WHERE {conditions} AND {table}.{id} {lower_bound} ? AND {table}.{id} <= ?
+                    ORDER BY {table}.{id} LIMIT {limit}",
+                    id = table.id_column,
+                    user_id = user_id_select,
+                    text = table.text_column,
+                    inline = inline_text_length(table),
+                    table = table.name,
+                    join = sync_state_join(table),
+                    conditions = filter.clause,
+                    lower_bound = lower_bound,
+                    limit = IMPORT_PAGE_SIZE
+                ),
+                page_values,
+            ))
+            .await?;
+        if items.is_empty() {
+            break;
+        }
+
+        let first_page_id: u64 = items[0].try_get("", "id")?;
+        {
+            let progress = progress.lock().await;
+            info!(
+                "Conducting import for {} starting from ID: {} on worker {} ({}/{} rows done)",
+                table.name, first_page_id, worker, progress.processed_rows, progress.total_rows
+            );
+        }
         let mut documents: Vec<VectorDbDocument> = Vec::new();
         let mut accumulated_size: usize = 0;
 
-        for item in items {
-            let primary_key_value = item[&primary_key_column].to_string();
-            let mut start_pos = 1;
-            let mut content = String::new();
-
-            loop {
-                let query = E::find()
-                    .select_only()
-                    .column_as(
-                        Expr::cust(&format!(
-                            "SUBSTRING({}, {}, {})",
-                            E::text_column().to_string(),
-                            start_pos,
-                            MAX_TEXT_CHUNK_SIZE
-                        )),
-                        "content_chunk",
-                    )
-                    .filter(E::primary_key_column().eq(&primary_key_value))
-                    .to_owned();
-
-                let content_chunk: Option<String> = query
-                    .into_json()
-                    .one(db)
-                    .await?
-                    .map(|json| json["content_chunk"].to_string());
-
-                if let Some(chunk) = content_chunk {
-                    let chunk_len = chunk.len();
-                    content.push_str(&chunk);
-                    start_pos += MAX_TEXT_CHUNK_SIZE;
-
-                    let mem_limit = std::env::var("MEM_LIMIT_MB")
-                        .ok()
-                        .and_then(|s| s.parse::<usize>().ok())
-                        .map(|mb| mb * 1024 * 1024)
-                        .unwrap_or(MEM_LIMIT);
-
-                    if chunk_len < MAX_TEXT_CHUNK_SIZE || accumulated_size + chunk_len >= mem_limit {
-                        let user_id: Option<u64> = item.get("user_id").and_then(|uid| uid.as_u64());
-
-                        let document = VectorDbDocument {
-                            id: string_to_i64(&primary_key_value),
-                            table_name: entity_table_name.to_string(),
-                            content: content.clone(),
-                            user_id,
-                        };
-                        documents.push(document);
-                        accumulated_size += content.len();
-                        content.clear();
-
-                        if accumulated_size >= mem_limit {
-                            create_and_save_embeddings(documents).await?;
-                            documents = Vec::new();
-                            accumulated_size = 0;
-                        }
-
-                        if chunk_len < MAX_TEXT_CHUNK_SIZE {
-                            break;
-                        }
-                    }
-                } else {
-                    break; // No more content
+        for item in &items {
+            let primary_key_value: u64 = item.try_get("", "id")?;
+            let user_id: Option<u64> = item
+                .try_get::<Option<i64>>("", "user_id")?
+                .map(|user_id| user_id as u64);
+
+            // The text is extracted from the row's file when it's embedded. NULL texts have
+            // nothing to embed, but are still synced so they stop counting as stale.
+            let null_text = item.try_get::<Option<i64>>("", "text_length")?.is_none();
+            if (table.file.is_some() && file_storage_configured()) || null_text {
+                documents.push(VectorDbDocument {
+                    id: primary_key_value as i64,
+                    table_name: table.name.to_string(),
+                    content: String::new(),
+                    user_id,
+                    page_offsets: Vec::new(),
+                });
+                continue;
+            }
+            if let Some(content) = item.try_get::<Option<String>>("", "content")? {
+                accumulated_size += content.len();
+                documents.push(VectorDbDocument {
+                    id: primary_key_value as i64,
+                    table_name: table.name.to_string(),
+                    content,
+                    user_id,
+                    page_offsets: Vec::new(),
+                });
+
+                if accumulated_size >= mem_limit {
+                    save_documents(documents, read_at, options, progress).await?;
+                    documents = Vec::new();
+                    accumulated_size = 0;
                 }
+                continue;
             }
+
+            // Too long to read with the page; read and embed it a window at a time
+            let document = VectorDbDocument {
+                id: primary_key_value as i64,
+                table_name: table.name.to_string(),
+                content: String::new(),
+                user_id,
+                page_offsets: Vec::new(),
+            };
+            save_streamed_document(document, table, read_at, options, progress).await?;
         }
 
         if !documents.is_empty() {
-            create_and_save_embeddings(documents).await?;
+            save_documents(documents, read_at, options, progress).await?;
         }
+
+        let page_last_id: u64 = items[items.len() - 1].try_get("", "id")?;
+        let mut progress = progress.lock().await;
+        progress.processed_rows += items.len() as u64;
+        progress.ranges[worker].last_id = Some(page_last_id);
+        // Saved under the lock, so checkpoints are never written out of order
+        progress.save().await?;
+        after_id = Some(page_last_id);
     }
 
+    let mut progress = progress.lock().await;
+    progress.ranges[worker].done = true;
+    progress.save().await?;
+
     Ok(())
 }
+
+// A table by name, as given on the command line
+pub fn lookup_table(name: &str) -> Result<&'static EmbeddableTable, ImportEmbeddingsError> {
+    embeddable_table(name).ok_or_else(|| {
+        ImportEmbeddingsError::UnknownTable(format!(
+            "{} (embeddable tables: {})",
+            name,
+            embeddable_table_names().join(", ")
+        ))
+    })
+}
+
+pub async fn import_table(name: &str, options: &ImportOptions) -> Result<(), ImportEmbeddingsError> {
+    import_embeddings(lookup_table(name)?, options).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn soft_deleted_table() -> EmbeddableTable {
+        EmbeddableTable {
+            deleted_at_column: Some("deleted_at"),
+            ..EmbeddableTable::test("posts")
+        }
+    }
+
+    fn options(scope: ImportScope) -> ImportOptions {
+        ImportOptions {
+            scope,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn row_filter_leaves_out_soft_deleted_rows() {
+        let filter = row_filter(&soft_deleted_table(), &options(ImportScope::All), None);
+
+        assert_eq!(filter.clause, "posts.deleted_at IS NULL");
+        assert!(filter.values.is_empty());
+    }
+
+    #[test]
+    fn row_filter_selects_everything_without_a_deleted_at_column() {
+        let filter = row_filter(&EmbeddableTable::test("posts"), &options(ImportScope::All), None);
+
+        assert_eq!(filter.clause, "TRUE");
+        assert!(filter.values.is_empty());
+    }
+
+    #[test]
+    fn row_filter_combines_the_start_the_resume_point_and_stale_rows() {
+        let options = ImportOptions {
+            start_from: Some(10),
+            ..options(ImportScope::Stale)
+        };
+        let (stale, stale_values) = stale_rows_filter(&EmbeddableTable::test("posts"));
+
+        let filter = row_filter(&soft_deleted_table(), &options, Some(42));
+
+        assert_eq!(
+            filter.clause,
+            format!(
+                "posts.deleted_at IS NULL AND posts.id >= ? AND posts.id > ? AND {}",
+                stale
+            )
+        );
+        let mut values: Vec<Value> = vec![10u64.into(), 42u64.into()];
+        values.extend(stale_values);
+        assert_eq!(filter.values, values);
+    }
+
+    #[test]
+    fn row_filter_adds_rows_synced_since_to_stale_ones() {
+        let since = Utc::now();
+        let (stale, _) = stale_rows_filter(&EmbeddableTable::test("posts"));
+
+        let filter = row_filter(
+            &EmbeddableTable::test("posts"),
+            &options(ImportScope::ChangedSince(since)),
+            None,
+        );
+
+        assert_eq!(
+            filter.clause,
+            format!("({} OR sync_state.synced_at >= ?)", stale)
+        );
+        assert_eq!(filter.values.last(), Some(&since.naive_utc().into()));
+    }
+
+    #[test]
+    fn split_id_range_splits_evenly() {
+        assert_eq!(
+            split_id_range(1, 100, 4),
+            vec![(1, 25), (26, 50), (51, 75), (76, 100)]
+        );
+    }
+
+    #[test]
+    fn split_id_range_shortens_the_last_range() {
+        assert_eq!(split_id_range(1, 10, 3), vec![(1, 4), (5, 8), (9, 10)]);
+    }
+
+    #[test]
+    fn split_id_range_makes_fewer_ranges_than_workers_for_few_ids() {
+        assert_eq!(split_id_range(5, 6, 4), vec![(5, 5), (6, 6)]);
+        assert_eq!(split_id_range(7, 7, 3), vec![(7, 7)]);
+    }
+}
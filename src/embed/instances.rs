@@ -1,6 +1,8 @@
+use std::collections::HashMap;
 use std::env;
 use async_openai::Client;
 use async_openai::config::OpenAIConfig;
+use backoff::{ExponentialBackoff, ExponentialBackoffBuilder};
 
 use sea_orm::{Database, DatabaseConnection};
 use tokenizers::{FromPretrainedParameters, PaddingParams, PaddingStrategy, Tokenizer, TruncationParams};
@@ -10,10 +12,19 @@ use qdrant_client::Qdrant;
 use log::info;
 use std::time::Duration;
 
+use crate::config::{get_config, EmbeddingProvider};
+use crate::embed::store::{
+    pgvector::PgvectorStore, qdrant::QdrantStore, retrying::RetryingStore, VectorStore,
+    VectorStoreBackend,
+};
+
 static QDRANT_CLIENT_INSTANCE: OnceCell<Qdrant> = OnceCell::const_new();
 static DB_POOL: OnceCell<DatabaseConnection> = OnceCell::const_new();
 static TOKENIZER: OnceCell<Tokenizer> = OnceCell::const_new();
 static EMBEDDING_CLIENT: OnceCell<Client<OpenAIConfig>> = OnceCell::const_new();
+static PROVIDER_CLIENTS: OnceCell<HashMap<String, Client<OpenAIConfig>>> = OnceCell::const_new();
+static PGVECTOR_DB_POOL: OnceCell<DatabaseConnection> = OnceCell::const_new();
+static VECTOR_STORE: OnceCell<Box<dyn VectorStore>> = OnceCell::const_new();
 
 pub const MODEL_NAME: &str = "silatus/gte-Qwen2-7B-instruct-INT4";
 
@@ -59,6 +70,26 @@ pub async fn get_tokenizer_instance() -> &'static Tokenizer {
         .await
 }
 
+// Tests can't download the model's tokenizer; this one counts whitespace-separated words
+#[cfg(test)]
+pub async fn use_word_tokenizer() {
+    use tokenizers::{models::wordlevel::WordLevel, pre_tokenizers::whitespace::WhitespaceSplit};
+
+    TOKENIZER
+        .get_or_init(|| async {
+            let model = WordLevel::builder()
+                .vocab(HashMap::from([("<unk>".to_string(), 0)]))
+                .unk_token("<unk>".to_string())
+                .build()
+                .unwrap();
+            let mut tokenizer = Tokenizer::new(model);
+            tokenizer.with_pre_tokenizer(WhitespaceSplit);
+
+            tokenizer
+        })
+        .await;
+}
+
 pub async fn get_qdrant_instance() -> &'static Qdrant {
     QDRANT_CLIENT_INSTANCE.get_or_init(|| async {
         info!("Creating Qdrant Client...");
@@ -77,6 +108,14 @@ pub async fn get_qdrant_instance() -> &'static Qdrant {
     }).await
 }
 
+// async-openai retries rate limits and 5xx for up to 15 minutes by itself; turn that
+// off so `with_retry` and its circuit breaker decide instead
+pub fn no_client_backoff() -> ExponentialBackoff {
+    ExponentialBackoffBuilder::new()
+        .with_max_elapsed_time(Some(Duration::ZERO))
+        .build()
+}
+
 pub async fn get_embedding_client_instance() -> &'static Client<OpenAIConfig> {
     EMBEDDING_CLIENT
         .get_or_init(|| async {
@@ -84,6 +123,69 @@ pub async fn get_embedding_client_instance() -> &'static Client<OpenAIConfig> {
                 .with_api_key(env::var("OPENAI_API_KEY").unwrap_or("EMPTY".to_string()))
                 .with_api_base(env::var("OPENAI_URL").unwrap_or("http://vecembed-model-service:8000/v1".to_string()));
 
-            Client::with_config(config)
+            Client::with_config(config).with_backoff(no_client_backoff())
         }).await
 }
+
+// Providers without their own url share the default embedding client
+pub async fn get_provider_client_instance(provider: &EmbeddingProvider) -> &'static Client<OpenAIConfig> {
+    if provider.url.is_none() {
+        return get_embedding_client_instance().await;
+    }
+
+    PROVIDER_CLIENTS
+        .get_or_init(|| async {
+            get_config()
+                .embedding
+                .providers
+                .iter()
+                .filter_map(|provider| {
+                    let url = provider.url.as_ref()?;
+                    let api_key = provider
+                        .api_key_env
+                        .as_ref()
+                        .and_then(|name| env::var(name).ok())
+                        .unwrap_or("EMPTY".to_string());
+                    let config = OpenAIConfig::new()
+                        .with_api_key(api_key)
+                        .with_api_base(url);
+
+                    Some((
+                        provider.name.clone(),
+                        Client::with_config(config).with_backoff(no_client_backoff()),
+                    ))
+                })
+                .collect()
+        })
+        .await
+        .get(&provider.name)
+        .expect("Every provider with a url has a client")
+}
+
+pub async fn get_pgvector_db_instance() -> &'static DatabaseConnection {
+    PGVECTOR_DB_POOL
+        .get_or_init(|| async {
+            Database::connect(env::var("PGVECTOR_DATABASE_URL").expect("pgvector DB URL not set"))
+                .await
+                .expect("Couldn't connect to pgvector DB")
+        })
+        .await
+}
+
+pub async fn get_vector_store_instance() -> &'static dyn VectorStore {
+    VECTOR_STORE
+        .get_or_init(|| async {
+            let store: Box<dyn VectorStore> = match VectorStoreBackend::from_env() {
+                VectorStoreBackend::Qdrant => Box::new(QdrantStore::new(get_qdrant_instance().await)),
+                VectorStoreBackend::Pgvector => {
+                    info!("Using pgvector as the vector store");
+                    Box::new(PgvectorStore::new(get_pgvector_db_instance().await))
+                }
+            };
+
+            let store: Box<dyn VectorStore> = Box::new(RetryingStore::new(store));
+            store
+        })
+        .await
+        .as_ref()
+}
@@ -0,0 +1,36 @@
+use whatlang::Lang;
+
+// Enough text to tell a document's language by, without reading all of it
+const DETECTION_SAMPLE_LENGTH: usize = 16 * 1024;
+// Languages written without spaces, and often without Western sentence punctuation
+const CJK_LANGUAGES: &[Lang] = &[Lang::Cmn, Lang::Jpn];
+const SENTENCE_ENDS: &[char] = &['.'];
+const CJK_SENTENCE_ENDS: &[char] = &['。', '！', '？', '．', '!', '?', '.'];
+
+// ISO 639-3 code of the text's language, e.g. "eng" or "cmn", when it can be told
+pub fn detect_language(text: &str) -> Option<&'static str> {
+    let mut end = text.len().min(DETECTION_SAMPLE_LENGTH);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    whatlang::detect(&text[..end])
+        .filter(|info| info.is_reliable())
+        .map(|info| info.lang().code())
+}
+
+// A chunk's own language, or its document's when the chunk is too short to tell
+pub fn chunk_language(
+    chunk_text: &str,
+    document_language: Option<&'static str>,
+) -> Option<&'static str> {
+    detect_language(chunk_text).or(document_language)
+}
+
+// Where the chunker prefers to end a chunk in text of the language
+pub fn sentence_ends(language: Option<&str>) -> &'static [char] {
+    match language.and_then(Lang::from_code) {
+        Some(lang) if CJK_LANGUAGES.contains(&lang) => CJK_SENTENCE_ENDS,
+        _ => SENTENCE_ENDS,
+    }
+}
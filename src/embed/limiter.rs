@@ -0,0 +1,244 @@
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use tokio::sync::Notify;
+
+const MAX_EMBEDDING_IN_FLIGHT: usize = 8;
+
+static EMBEDDING_LIMITER: OnceLock<EmbeddingLimiter> = OnceLock::new();
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Priority {
+    // Retrieval queries, someone is waiting on the answer
+    Query,
+    // gRPC ingest and imports
+    Ingest,
+}
+
+struct LimiterState {
+    in_flight: usize,
+    queries_waiting: usize,
+}
+
+struct TokenBucket {
+    available: f64,
+    updated_at: Instant,
+}
+
+// Caps how hard every embedding caller together can push the model server: requests
+// in flight and tokens per second. Queries skip the queue ahead of ingest for both.
+pub struct EmbeddingLimiter {
+    max_in_flight: usize,
+    tokens_per_second: Option<f64>,
+    state: Mutex<LimiterState>,
+    bucket: Mutex<TokenBucket>,
+    released: Notify,
+}
+
+// Holds an in-flight slot until the request is done
+pub struct EmbeddingPermit {
+    limiter: &'static EmbeddingLimiter,
+}
+
+impl Drop for EmbeddingPermit {
+    fn drop(&mut self) {
+        self.limiter.state.lock().unwrap().in_flight -= 1;
+        self.limiter.released.notify_waiters();
+    }
+}
+
+// Keeps ingest out of free slots while a query waits, even if the query gets cancelled
+struct QueryWaiting {
+    limiter: &'static EmbeddingLimiter,
+}
+
+impl QueryWaiting {
+    fn new(limiter: &'static EmbeddingLimiter) -> Self {
+        limiter.state.lock().unwrap().queries_waiting += 1;
+        QueryWaiting { limiter }
+    }
+}
+
+impl Drop for QueryWaiting {
+    fn drop(&mut self) {
+        self.limiter.state.lock().unwrap().queries_waiting -= 1;
+        self.limiter.released.notify_waiters();
+    }
+}
+
+impl EmbeddingLimiter {
+    fn from_env() -> Self {
+        let max_in_flight = std::env::var("MAX_EMBEDDING_IN_FLIGHT")
+            .ok()
+            .and_then(|s| s.parse::<usize>().ok())
+            .unwrap_or(MAX_EMBEDDING_IN_FLIGHT)
+            .max(1);
+        let tokens_per_second = std::env::var("MAX_EMBEDDING_TOKENS_PER_SECOND")
+            .ok()
+            .and_then(|s| s.parse::<f64>().ok())
+            .filter(|rate| *rate > 0.0);
+
+        EmbeddingLimiter::new(max_in_flight, tokens_per_second)
+    }
+
+    fn new(max_in_flight: usize, tokens_per_second: Option<f64>) -> Self {
+        EmbeddingLimiter {
+            max_in_flight,
+            tokens_per_second,
+            state: Mutex::new(LimiterState {
+                in_flight: 0,
+                queries_waiting: 0,
+            }),
+            bucket: Mutex::new(TokenBucket {
+                available: tokens_per_second.unwrap_or_default(),
+                updated_at: Instant::now(),
+            }),
+            released: Notify::new(),
+        }
+    }
+
+    // Waits for both the token budget and a free slot. Tokens come first so a request
+    // waiting on the budget doesn't sit on a slot a query could use.
+    pub async fn acquire(&'static self, priority: Priority, tokens: usize) -> EmbeddingPermit {
+        self.take_tokens(priority, tokens).await;
+
+        let _waiting = (priority == Priority::Query).then(|| QueryWaiting::new(self));
+        loop {
+            // Registered before checking, so a release in between isn't missed
+            let released = self.released.notified();
+            tokio::pin!(released);
+            released.as_mut().enable();
+
+            if self.try_start(priority) {
+                return EmbeddingPermit { limiter: self };
+            }
+            released.await;
+        }
+    }
+
+    fn try_start(&self, priority: Priority) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.in_flight >= self.max_in_flight
+            || (priority == Priority::Ingest && state.queries_waiting > 0)
+        {
+            return false;
+        }
+
+        state.in_flight += 1;
+        true
+    }
+
+    async fn take_tokens(&self, priority: Priority, tokens: usize) {
+        let Some(rate) = self.tokens_per_second else {
+            return;
+        };
+        let tokens = tokens as f64;
+
+        loop {
+            let wait = {
+                let mut bucket = self.bucket.lock().unwrap();
+                let now = Instant::now();
+                let refill = rate * now.duration_since(bucket.updated_at).as_secs_f64();
+                bucket.available = (bucket.available + refill).min(rate);
+                bucket.updated_at = now;
+
+                // A batch bigger than a second's budget only has to wait for a full bucket.
+                // Queries never wait; the debt they leave is paid off by ingest.
+                let needed = tokens.min(rate);
+                if priority == Priority::Query || bucket.available >= needed {
+                    bucket.available -= tokens;
+                    return;
+                }
+
+                Duration::from_secs_f64((needed - bucket.available) / rate)
+            };
+
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+pub fn get_embedding_limiter() -> &'static EmbeddingLimiter {
+    EMBEDDING_LIMITER.get_or_init(EmbeddingLimiter::from_env)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SETTLE: Duration = Duration::from_millis(50);
+
+    fn limiter(max_in_flight: usize, tokens_per_second: Option<f64>) -> &'static EmbeddingLimiter {
+        Box::leak(Box::new(EmbeddingLimiter::new(
+            max_in_flight,
+            tokens_per_second,
+        )))
+    }
+
+    #[tokio::test]
+    async fn in_flight_cap_holds_until_a_permit_is_dropped() {
+        let limiter = limiter(2, None);
+        let first = limiter.acquire(Priority::Ingest, 1).await;
+        let _second = limiter.acquire(Priority::Query, 1).await;
+
+        let third = tokio::spawn(limiter.acquire(Priority::Query, 1));
+        tokio::time::sleep(SETTLE).await;
+        assert!(!third.is_finished());
+        assert_eq!(limiter.state.lock().unwrap().in_flight, 2);
+
+        drop(first);
+        let _third = tokio::time::timeout(SETTLE, third).await.unwrap().unwrap();
+        assert_eq!(limiter.state.lock().unwrap().in_flight, 2);
+    }
+
+    #[tokio::test]
+    async fn waiting_query_goes_before_queued_ingest() {
+        let limiter = limiter(1, None);
+        let held = limiter.acquire(Priority::Query, 1).await;
+
+        let ingest = tokio::spawn(limiter.acquire(Priority::Ingest, 1));
+        tokio::time::sleep(SETTLE).await;
+        let query = tokio::spawn(limiter.acquire(Priority::Query, 1));
+        tokio::time::sleep(SETTLE).await;
+
+        drop(held);
+        let query_permit = tokio::time::timeout(SETTLE, query).await.unwrap().unwrap();
+        tokio::time::sleep(SETTLE).await;
+        assert!(!ingest.is_finished());
+
+        drop(query_permit);
+        tokio::time::timeout(SETTLE, ingest).await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn cancelled_query_stops_holding_back_ingest() {
+        let limiter = limiter(1, None);
+        let held = limiter.acquire(Priority::Query, 1).await;
+
+        let ingest = tokio::spawn(limiter.acquire(Priority::Ingest, 1));
+        let query = tokio::spawn(limiter.acquire(Priority::Query, 1));
+        tokio::time::sleep(SETTLE).await;
+        query.abort();
+        tokio::time::sleep(SETTLE).await;
+
+        drop(held);
+        tokio::time::timeout(SETTLE, ingest).await.unwrap().unwrap();
+        assert_eq!(limiter.state.lock().unwrap().queries_waiting, 0);
+    }
+
+    #[tokio::test]
+    async fn request_bigger_than_the_bucket_only_waits_for_a_full_bucket() {
+        let limiter = limiter(8, Some(1000.0));
+        // Queries never wait, so this empties the bucket
+        drop(limiter.acquire(Priority::Query, 1000).await);
+
+        let started = Instant::now();
+        let permit = tokio::time::timeout(
+            Duration::from_secs(2),
+            limiter.acquire(Priority::Ingest, 10_000),
+        )
+        .await;
+        assert!(permit.is_ok());
+        assert!(started.elapsed() >= Duration::from_millis(900));
+    }
+}
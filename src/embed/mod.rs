@@ -1,7 +1,22 @@
+pub mod checkpoint;
 pub mod chunk_strings;
+pub mod coalescer;
 pub mod collections;
 pub mod create;
+pub mod document_text;
+pub mod duplicates;
+pub mod embedder;
 pub mod errors;
+pub mod estimate;
+pub mod extract;
 pub mod get;
 pub mod import;
-mod instances;
+pub mod instances;
+pub mod language;
+pub mod limiter;
+pub mod reindex;
+pub mod retry;
+pub mod store;
+pub mod structure;
+pub mod sync_state;
+pub mod tables;
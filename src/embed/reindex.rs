@@ -0,0 +1,297 @@
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use sea_orm::{ConnectionTrait, DatabaseBackend, Statement, Value};
+
+use super::{
+    collections::write_collection,
+    create::{live_target, IngestTarget},
+    estimate::ImportEstimate,
+    import::{
+        estimate_import, import_embeddings, CheckpointMode, ImportEmbeddingsError, ImportOptions,
+        ImportScope,
+    },
+    instances::{get_db_instance, get_vector_store_instance},
+    sync_state::SYNC_STATE,
+    tables::{EmbeddableTable, EMBEDDABLE_TABLES},
+};
+use crate::config::get_config;
+
+// Documents deleted from the vector store per request
+const DELETE_BATCH_SIZE: usize = 500;
+
+// The unversioned base collection counts as version 1
+fn collection_version(base: &str, collection: &str) -> Option<u64> {
+    if collection == base {
+        return Some(1);
+    }
+
+    collection
+        .strip_prefix(base)
+        .and_then(|suffix| suffix.strip_prefix("_v"))
+        // `parse` would take a sign too
+        .filter(|version| version.bytes().all(|b| b.is_ascii_digit()))
+        .and_then(|version| version.parse::<u64>().ok())
+}
+
+// Rows a reindex may have embedded before they were deleted: soft deleted since it
+// started, or gone from the table with their sync state left behind
+fn deleted_rows_query(table: &EmbeddableTable, since: DateTime<Utc>) -> (String, Vec<Value>) {
+    let mut queries = Vec::new();
+    let mut values: Vec<Value> = Vec::new();
+    if let Some(deleted_at) = table.deleted_at_column {
+        queries.push(format!(
+            "SELECT CAST({table}.{id} AS SIGNED) AS id FROM {table} WHERE {table}.{deleted_at} >= ?",
+            table = table.name,
+            id = table.id_column,
+            deleted_at = deleted_at
+        ));
+        values.push(since.naive_utc().into());
+    }
+    queries.push(format!(
+        "SELECT {state}.row_id AS id FROM vecembed_sync_state AS {state}
+        LEFT JOIN {table} ON {table}.{id} = {state}.row_id
+        WHERE {state}.table_name = '{table}' AND {table}.{id} IS NULL",
+        state = SYNC_STATE,
+        table = table.name,
+        id = table.id_column
+    ));
+
+    (queries.join(" UNION "), values)
+}
+
+// The catch-up only re-embeds rows that are still there, so rows deleted during the
+// reindex are removed from the new version here
+async fn remove_deleted_rows(
+    table: &EmbeddableTable,
+    since: DateTime<Utc>,
+    collection: &str,
+) -> Result<(), ImportEmbeddingsError> {
+    let db = get_db_instance().await;
+    let (sql, values) = deleted_rows_query(table, since);
+    let keys = db
+        .query_all(Statement::from_sql_and_values(DatabaseBackend::MySql, sql, values))
+        .await?
+        .into_iter()
+        .map(|row| Ok((table.name.to_string(), row.try_get::<i64>("", "id")?)))
+        .collect::<Result<Vec<_>, ImportEmbeddingsError>>()?;
+    if keys.is_empty() {
+        return Ok(());
+    }
+
+    info!("Removing {} rows of {} deleted during the reindex", keys.len(), table.name);
+    let store = get_vector_store_instance().await;
+    for batch in keys.chunks(DELETE_BATCH_SIZE) {
+        store.delete_documents(collection, batch).await?;
+    }
+
+    Ok(())
+}
+
+fn configured_alias() -> Result<&'static str, ImportEmbeddingsError> {
+    get_config().alias.as_deref().ok_or_else(|| {
+        ImportEmbeddingsError::Reindex(
+            "set `alias` in the config file so retrieval can be switched between versions"
+                .to_string(),
+        )
+    })
+}
+
+// What a reindex would take, from chunking every table without embedding it
+pub async fn estimate_reindex() -> Result<ImportEstimate, ImportEmbeddingsError> {
+    let options = ImportOptions {
+        scope: ImportScope::All,
+        checkpoint: CheckpointMode::Off,
+        ..Default::default()
+    };
+
+    let mut total = ImportEstimate::default();
+    for table in EMBEDDABLE_TABLES {
+        total.add(&estimate_import(table, &options).await?);
+    }
+    info!("Dry run of the reindex: {}", total);
+
+    Ok(total)
+}
+
+// Builds a new version of the collection from SQL, then points the alias at it
+pub async fn reindex() -> Result<(), ImportEmbeddingsError> {
+    let config = get_config();
+    let alias = configured_alias()?;
+    let store = get_vector_store_instance().await;
+
+    let next_version = store
+        .list_collections()
+        .await?
+        .iter()
+        .filter_map(|collection| collection_version(&config.collection, collection))
+        .max()
+        .unwrap_or(0)
+        + 1;
+
+    let mut collection = config.active_collection().clone();
+    collection.name = format!("{}_v{}", config.collection, next_version);
+    let new_collection = collection.name.clone();
+
+    info!("Reindexing into {}", new_collection);
+    let started_at = Utc::now();
+
+    let options = ImportOptions {
+        scope: ImportScope::All,
+        target: Some(IngestTarget {
+            collection,
+            mark_synced: false,
+            skip_unchanged: false,
+        }),
+        checkpoint: CheckpointMode::Off,
+        ..Default::default()
+    };
+    for (index, table) in EMBEDDABLE_TABLES.iter().enumerate() {
+        info!(
+            "Reindexing table {} ({}/{})",
+            table.name,
+            index + 1,
+            EMBEDDABLE_TABLES.len()
+        );
+        import_embeddings(table, &options).await?;
+    }
+
+    if !store.collection_exists(&new_collection).await? {
+        return Err(ImportEmbeddingsError::Reindex(format!(
+            "nothing was embedded into {}, keeping the current version",
+            new_collection
+        )));
+    }
+
+    let previous = store.resolve_alias(alias).await?;
+    store.switch_alias(alias, &new_collection).await?;
+    info!(
+        "Alias {} now points at {} (previously {})",
+        alias,
+        new_collection,
+        previous.as_deref().unwrap_or("nothing")
+    );
+
+    // Rows the live service embedded while we were building went into the old version,
+    // so they're embedded again even where their sync state says they're unchanged
+    let catch_up = ImportOptions {
+        scope: ImportScope::ChangedSince(started_at),
+        target: Some(IngestTarget {
+            skip_unchanged: false,
+            ..live_target().await?
+        }),
+        checkpoint: CheckpointMode::Off,
+        ..Default::default()
+    };
+    for table in EMBEDDABLE_TABLES {
+        info!("Catching up rows of {} changed during the reindex", table.name);
+        import_embeddings(table, &catch_up).await?;
+        remove_deleted_rows(table, started_at, &new_collection).await?;
+    }
+
+    Ok(())
+}
+
+// Points the alias back at the version before the current one
+pub async fn rollback() -> Result<(), ImportEmbeddingsError> {
+    let config = get_config();
+    let alias = configured_alias()?;
+    let store = get_vector_store_instance().await;
+
+    let current = write_collection().await?.name;
+    let current_version = collection_version(&config.collection, &current).ok_or_else(|| {
+        ImportEmbeddingsError::Reindex(format!(
+            "alias {} points at {}, which isn't a version of {}",
+            alias, current, config.collection
+        ))
+    })?;
+
+    let previous = store
+        .list_collections()
+        .await?
+        .into_iter()
+        .filter_map(|collection| {
+            collection_version(&config.collection, &collection)
+                .filter(|version| *version < current_version)
+                .map(|version| (version, collection))
+        })
+        .max_by_key(|(version, _)| *version)
+        .map(|(_, collection)| collection)
+        .ok_or_else(|| {
+            ImportEmbeddingsError::Reindex(format!("no version older than {} exists", current))
+        })?;
+
+    store.switch_alias(alias, &previous).await?;
+    info!("Alias {} rolled back from {} to {}", alias, current, previous);
+    warn!(
+        "Rows embedded into {} after it went live are missing from {}",
+        current, previous
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deleted_rows_query_finds_soft_deleted_rows_since_the_reindex_started() {
+        let since = Utc::now();
+        let table = EmbeddableTable {
+            deleted_at_column: Some("deleted_at"),
+            ..EmbeddableTable::test("posts")
+        };
+        let (sql, values) = deleted_rows_query(&table, since);
+
+        assert!(sql.starts_with(
+            "SELECT CAST(posts.id AS SIGNED) AS id FROM posts WHERE posts.deleted_at >= ? UNION "
+        ));
+        assert_eq!(values, vec![Value::from(since.naive_utc())]);
+    }
+
+    #[test]
+    fn deleted_rows_query_finds_rows_gone_from_the_table() {
+        let (sql, values) = deleted_rows_query(&EmbeddableTable::test("posts"), Utc::now());
+
+        assert!(sql.starts_with("SELECT sync_state.row_id AS id FROM vecembed_sync_state"));
+        assert!(sql.contains("LEFT JOIN posts ON posts.id = sync_state.row_id"));
+        assert!(sql.contains("sync_state.table_name = 'posts' AND posts.id IS NULL"));
+        assert!(values.is_empty());
+    }
+
+    #[test]
+    fn collection_version_counts_the_base_collection_as_version_1() {
+        assert_eq!(collection_version("silatus_documents", "silatus_documents"), Some(1));
+    }
+
+    #[test]
+    fn collection_version_reads_multi_digit_versions() {
+        assert_eq!(collection_version("silatus_documents", "silatus_documents_v2"), Some(2));
+        assert_eq!(collection_version("silatus_documents", "silatus_documents_v123"), Some(123));
+    }
+
+    #[test]
+    fn collection_version_ignores_foreign_collections() {
+        for collection in [
+            "other_documents",
+            "other_documents_v2",
+            "silatus_documents_old",
+            "silatus_documents_v2_backup",
+            "silatus_documentsv2",
+        ] {
+            assert_eq!(collection_version("silatus_documents", collection), None, "{}", collection);
+        }
+    }
+
+    #[test]
+    fn collection_version_needs_a_number_after_v() {
+        for collection in [
+            "silatus_documents_v",
+            "silatus_documents_vx",
+            "silatus_documents_v+2",
+            "silatus_documents_v-2",
+        ] {
+            assert_eq!(collection_version("silatus_documents", collection), None, "{}", collection);
+        }
+    }
+}
@@ -0,0 +1,435 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use async_openai::error::OpenAIError;
+use rand::Rng;
+use sea_orm::{DbErr, RuntimeErr};
+
+use super::errors::{EmbeddingError, QdrantClientError};
+use crate::config::get_config;
+
+const MAX_RETRIES: u32 = 3;
+const RETRY_BASE_DELAY_MS: u64 = 250;
+const RETRY_MAX_DELAY_MS: u64 = 10_000;
+const CIRCUIT_BREAKER_THRESHOLD: u32 = 5;
+const CIRCUIT_BREAKER_COOLDOWN_SECS: u64 = 30;
+
+static EMBEDDING_BREAKERS: OnceLock<HashMap<String, CircuitBreaker>> = OnceLock::new();
+pub static VECTOR_STORE_BREAKER: CircuitBreaker = CircuitBreaker::new("vector store");
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    std::env::var(name)
+        .ok()
+        .and_then(|s| s.parse::<T>().ok())
+        .unwrap_or(default)
+}
+
+// Opens after enough consecutive transient failures and fails every call fast until
+// the cooldown passes. The first call after that is let through as a probe, while the
+// rest keep failing fast for another cooldown: the probe succeeding closes the breaker
+// again, failing reopens it.
+pub struct CircuitBreaker {
+    name: Cow<'static, str>,
+    consecutive_failures: AtomicU32,
+    open_until: Mutex<Option<Instant>>,
+}
+
+impl CircuitBreaker {
+    pub const fn new(name: &'static str) -> Self {
+        CircuitBreaker {
+            name: Cow::Borrowed(name),
+            consecutive_failures: AtomicU32::new(0),
+            open_until: Mutex::new(None),
+        }
+    }
+
+    fn check(&self) -> Result<(), EmbeddingError> {
+        let mut open_until = self.open_until.lock().unwrap();
+        match *open_until {
+            Some(until) if Instant::now() < until => Err(EmbeddingError::BackendUnavailable(format!(
+                "{} is failing, retrying in {}s",
+                self.name,
+                until.saturating_duration_since(Instant::now()).as_secs()
+            ))),
+            // Half open: this call is the probe, so keep everyone else out until it's done
+            // or, should it never finish, until the cooldown passes again
+            Some(_) => {
+                *open_until = Some(Instant::now() + cooldown());
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn record_success(&self) {
+        self.consecutive_failures.store(0, Ordering::SeqCst);
+        *self.open_until.lock().unwrap() = None;
+    }
+
+    fn record_failure(&self) {
+        let failures = self.consecutive_failures.fetch_add(1, Ordering::SeqCst) + 1;
+        if failures < env_or("CIRCUIT_BREAKER_THRESHOLD", CIRCUIT_BREAKER_THRESHOLD) {
+            return;
+        }
+
+        let cooldown = cooldown();
+        let mut open_until = self.open_until.lock().unwrap();
+        if open_until.is_none() {
+            log::error!(
+                "{} failed {} times in a row, failing fast for {}s",
+                self.name,
+                failures,
+                cooldown.as_secs()
+            );
+        }
+        *open_until = Some(Instant::now() + cooldown);
+    }
+}
+
+fn cooldown() -> Duration {
+    Duration::from_secs(env_or(
+        "CIRCUIT_BREAKER_COOLDOWN_SECS",
+        CIRCUIT_BREAKER_COOLDOWN_SECS,
+    ))
+}
+
+// Each embedding provider is its own server, so each gets its own breaker
+pub fn embedding_breaker(provider_name: &str) -> &'static CircuitBreaker {
+    static FALLBACK: CircuitBreaker = CircuitBreaker::new("embedding server");
+
+    EMBEDDING_BREAKERS
+        .get_or_init(|| {
+            get_config()
+                .embedding
+                .vector_providers()
+                .into_iter()
+                .map(|provider| {
+                    let breaker = CircuitBreaker {
+                        name: Cow::Owned(format!("embedding server `{}`", provider.name)),
+                        consecutive_failures: AtomicU32::new(0),
+                        open_until: Mutex::new(None),
+                    };
+                    (provider.name, breaker)
+                })
+                .collect()
+        })
+        .get(provider_name)
+        .unwrap_or(&FALLBACK)
+}
+
+// Errors worth another attempt: timeouts, dropped connections, rate limits and 5xx responses
+pub fn is_retryable(err: &EmbeddingError) -> bool {
+    if is_batch_too_large(err) {
+        return false;
+    }
+
+    match err {
+        EmbeddingError::OpenAIError(OpenAIError::Reqwest(err)) => {
+            err.is_timeout()
+                || err.is_connect()
+                || err
+                    .status()
+                    .is_some_and(|status| status.as_u16() == 429 || status.is_server_error())
+        }
+        // async-openai reports 5xx responses as API errors without an error type
+        EmbeddingError::OpenAIError(OpenAIError::ApiError(err)) => err.r#type.is_none(),
+        EmbeddingError::QdrantClient(QdrantClientError::Unavailable(_)) => true,
+        EmbeddingError::DbError(DbErr::Conn(_) | DbErr::ConnectionAcquire(_)) => true,
+        _ => false,
+    }
+}
+
+// The server refusing a batch for its size, which no number of retries will fix
+pub fn is_batch_too_large(err: &EmbeddingError) -> bool {
+    match err {
+        EmbeddingError::OpenAIError(OpenAIError::Reqwest(err)) => {
+            err.status().is_some_and(|status| status.as_u16() == 413)
+        }
+        EmbeddingError::OpenAIError(OpenAIError::ApiError(err)) => {
+            let message = err.message.to_lowercase();
+            [
+                "maximum context length",
+                "max_num_batched_tokens",
+                "too many tokens",
+                "too large",
+                "too long",
+            ]
+            .iter()
+            .any(|pattern| message.contains(pattern))
+        }
+        _ => false,
+    }
+}
+
+// Errors the backend responded with, as opposed to ones raised before or without a
+// response, like serialization or dimension errors
+fn answered_by_backend(err: &EmbeddingError) -> bool {
+    match err {
+        EmbeddingError::OpenAIError(OpenAIError::ApiError(_)) => true,
+        EmbeddingError::OpenAIError(OpenAIError::Reqwest(err)) => err.status().is_some(),
+        EmbeddingError::QdrantClient(QdrantClientError::Rejected(_)) => true,
+        // sea-orm doesn't expose sqlx's error kinds, only how they display
+        EmbeddingError::DbError(
+            DbErr::Exec(RuntimeErr::SqlxError(err)) | DbErr::Query(RuntimeErr::SqlxError(err)),
+        ) => err.to_string().starts_with("error returned from database"),
+        _ => false,
+    }
+}
+
+// Exponential backoff with equal jitter, so retrying clients spread out instead of
+// hitting a recovering server in lockstep
+fn backoff_delay(attempt: u32) -> Duration {
+    let base = env_or("RETRY_BASE_DELAY_MS", RETRY_BASE_DELAY_MS);
+    let max = env_or("RETRY_MAX_DELAY_MS", RETRY_MAX_DELAY_MS);
+    let delay = base.saturating_mul(1 << attempt.min(16)).min(max);
+
+    Duration::from_millis(delay / 2 + rand::thread_rng().gen_range(0..=delay / 2))
+}
+
+pub async fn with_retry<T, F, Fut>(
+    breaker: &CircuitBreaker,
+    mut operation: F,
+) -> Result<T, EmbeddingError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, EmbeddingError>>,
+{
+    let max_retries = env_or("MAX_RETRIES", MAX_RETRIES);
+    let mut attempt = 0;
+
+    loop {
+        breaker.check()?;
+
+        match operation().await {
+            Ok(value) => {
+                breaker.record_success();
+                return Ok(value);
+            }
+            Err(err) if is_retryable(&err) => {
+                breaker.record_failure();
+                if attempt >= max_retries {
+                    return Err(err);
+                }
+
+                let delay = backoff_delay(attempt);
+                attempt += 1;
+                log::warn!(
+                    "{} call failed: {}; retrying in {}ms ({}/{})",
+                    breaker.name,
+                    err,
+                    delay.as_millis(),
+                    attempt,
+                    max_retries
+                );
+                tokio::time::sleep(delay).await;
+            }
+            Err(err) => {
+                // Answering at all means the backend is up; errors raised on our side
+                // say nothing about it either way
+                if answered_by_backend(&err) {
+                    breaker.record_success();
+                }
+                return Err(err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_openai::{config::OpenAIConfig, types::CreateEmbeddingRequestArgs, Client};
+    use mockito::{Matcher, Server, ServerGuard};
+
+    use super::*;
+    use crate::embed::instances::no_client_backoff;
+
+    const EMBEDDINGS: &str = r#"{"object":"list","data":[{"object":"embedding","embedding":[0.6,0.8],"index":0}],"model":"test","usage":{"prompt_tokens":1,"total_tokens":1}}"#;
+
+    fn api_error(error_type: Option<&str>) -> String {
+        serde_json::json!({
+            "error": {"message": "failed", "type": error_type, "param": null, "code": null}
+        })
+        .to_string()
+    }
+
+    fn mock_embeddings(server: &mut ServerGuard, status: usize, body: &str, hits: usize) -> mockito::Mock {
+        server
+            .mock("POST", "/embeddings")
+            .match_body(Matcher::Any)
+            .with_status(status)
+            .with_header("content-type", "application/json")
+            .with_body(body)
+            .expect(hits)
+            .create()
+    }
+
+    // Embeds through a real client against the fake server, the way the embedder does
+    async fn embed(server: &ServerGuard, breaker: &CircuitBreaker) -> Result<usize, EmbeddingError> {
+        let client = Client::with_config(
+            OpenAIConfig::new()
+                .with_api_key("EMPTY")
+                .with_api_base(server.url()),
+        )
+        .with_backoff(no_client_backoff());
+
+        with_retry(breaker, || async {
+            let request = CreateEmbeddingRequestArgs::default()
+                .model("test")
+                .input(vec!["hello".to_string()])
+                .build()?;
+
+            Ok(client.embeddings().create(request).await?.data.len())
+        })
+        .await
+    }
+
+    fn open(breaker: &CircuitBreaker) {
+        for _ in 0..CIRCUIT_BREAKER_THRESHOLD {
+            breaker.record_failure();
+        }
+    }
+
+    fn expire_cooldown(breaker: &CircuitBreaker) {
+        *breaker.open_until.lock().unwrap() = Some(Instant::now() - Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn retries_server_errors_until_one_succeeds() {
+        let mut server = Server::new_async().await;
+        let failures = mock_embeddings(&mut server, 503, &api_error(None), 2);
+        let success = mock_embeddings(&mut server, 200, EMBEDDINGS, 1);
+
+        let breaker = CircuitBreaker::new("test");
+        assert_eq!(embed(&server, &breaker).await.unwrap(), 1);
+
+        failures.assert_async().await;
+        success.assert_async().await;
+        assert_eq!(breaker.consecutive_failures.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn retries_rate_limits() {
+        let mut server = Server::new_async().await;
+        let rate_limited = mock_embeddings(&mut server, 429, &api_error(None), 1);
+        let success = mock_embeddings(&mut server, 200, EMBEDDINGS, 1);
+
+        let breaker = CircuitBreaker::new("test");
+        assert_eq!(embed(&server, &breaker).await.unwrap(), 1);
+
+        rate_limited.assert_async().await;
+        success.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_the_last_retry() {
+        let mut server = Server::new_async().await;
+        let failures = mock_embeddings(&mut server, 500, &api_error(None), MAX_RETRIES as usize + 1);
+
+        let breaker = CircuitBreaker::new("test");
+        let err = embed(&server, &breaker).await.unwrap_err();
+
+        assert!(is_retryable(&err));
+        failures.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn doesnt_retry_client_errors() {
+        let mut server = Server::new_async().await;
+        let rejected = mock_embeddings(&mut server, 400, &api_error(Some("invalid_request_error")), 1);
+
+        let breaker = CircuitBreaker::new("test");
+        let err = embed(&server, &breaker).await.unwrap_err();
+
+        assert!(!is_retryable(&err));
+        rejected.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn open_breaker_fails_fast_without_calling_the_server() {
+        let mut server = Server::new_async().await;
+        let untouched = mock_embeddings(&mut server, 200, EMBEDDINGS, 0);
+
+        let breaker = CircuitBreaker::new("test");
+        open(&breaker);
+        let err = embed(&server, &breaker).await.unwrap_err();
+
+        assert!(matches!(err, EmbeddingError::BackendUnavailable(_)));
+        untouched.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn successful_probe_closes_the_breaker() {
+        let mut server = Server::new_async().await;
+        let success = mock_embeddings(&mut server, 200, EMBEDDINGS, 2);
+
+        let breaker = CircuitBreaker::new("test");
+        open(&breaker);
+        expire_cooldown(&breaker);
+        assert_eq!(embed(&server, &breaker).await.unwrap(), 1);
+        assert_eq!(embed(&server, &breaker).await.unwrap(), 1);
+
+        success.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn probe_the_server_rejects_still_closes_the_breaker() {
+        let mut server = Server::new_async().await;
+        let rejected = mock_embeddings(&mut server, 400, &api_error(Some("invalid_request_error")), 1);
+
+        let breaker = CircuitBreaker::new("test");
+        open(&breaker);
+        expire_cooldown(&breaker);
+        assert!(embed(&server, &breaker).await.is_err());
+
+        rejected.assert_async().await;
+        assert!(breaker.check().is_ok());
+    }
+
+    #[tokio::test]
+    async fn errors_raised_locally_leave_the_breaker_alone() {
+        let breaker = CircuitBreaker::new("test");
+        open(&breaker);
+        expire_cooldown(&breaker);
+
+        let err = with_retry(&breaker, || async {
+            Err::<(), _>(EmbeddingError::DimensionMismatch {
+                collection: "test".to_string(),
+                expected: 2,
+                actual: 3,
+            })
+        })
+        .await
+        .unwrap_err();
+
+        assert!(matches!(err, EmbeddingError::DimensionMismatch { .. }));
+        assert!(breaker.check().is_err());
+        assert_eq!(
+            breaker.consecutive_failures.load(Ordering::SeqCst),
+            CIRCUIT_BREAKER_THRESHOLD
+        );
+    }
+
+    #[test]
+    fn only_one_probe_is_let_through_after_the_cooldown() {
+        let breaker = CircuitBreaker::new("test");
+        open(&breaker);
+        assert!(breaker.check().is_err());
+
+        expire_cooldown(&breaker);
+        assert!(breaker.check().is_ok());
+        assert!(breaker.check().is_err());
+
+        // A failed probe reopens the breaker, a successful one closes it
+        breaker.record_failure();
+        assert!(breaker.check().is_err());
+        expire_cooldown(&breaker);
+        assert!(breaker.check().is_ok());
+        breaker.record_success();
+        assert!(breaker.check().is_ok());
+        assert!(breaker.check().is_ok());
+    }
+}
@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+
+use qdrant_client::qdrant::SearchParams;
+
+use super::{document_text::DocumentKey, errors::EmbeddingError};
+use crate::config::CollectionConfig;
+
+pub mod pgvector;
+pub mod qdrant;
+pub mod retrying;
+
+pub type StorePayload = HashMap<String, serde_json::Value>;
+
+// Embeddings of one chunk by vector name; `None` is a collection's single unnamed vector
+pub type PointVectors = HashMap<Option<String>, Vec<f32>>;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VectorSpec {
+    pub name: Option<String>,
+    pub dimension: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct VectorPoint {
+    pub id: String,
+    pub vectors: PointVectors,
+    pub payload: StorePayload,
+}
+
+#[derive(Clone, Debug)]
+pub struct ScoredChunk {
+    pub score: f32,
+    pub payload: StorePayload,
+}
+
+// A set of chunks a search is allowed to return. Scopes are OR'ed together,
+// while every field set inside a scope must match.
+#[derive(Clone, Debug, Default)]
+pub struct SearchScope {
+    pub table_name: String,
+    pub user_id: Option<i64>,
+    pub document_ids: Vec<i64>,
+    // ISO 639-3 codes of the languages chunks may be in; empty allows any
+    pub languages: Vec<String>,
+}
+
+// Examples to recommend chunks by: results are like the positive chunks, unlike the
+// negative ones, and never in one of the excluded scopes
+#[derive(Clone, Debug, Default)]
+pub struct Recommendation {
+    pub positive: Vec<String>,
+    pub negative: Vec<String>,
+    pub excluded: Vec<SearchScope>,
+}
+
+// One scope per table of the documents, as ids are only unique within their table
+pub fn document_scopes(documents: &[DocumentKey]) -> Vec<SearchScope> {
+    let mut ids_by_table: HashMap<&str, Vec<i64>> = HashMap::new();
+    for (table_name, id) in documents {
+        ids_by_table.entry(table_name.as_str()).or_default().push(*id);
+    }
+
+    ids_by_table
+        .into_iter()
+        .map(|(table_name, document_ids)| SearchScope {
+            table_name: table_name.to_string(),
+            document_ids,
+            ..Default::default()
+        })
+        .collect()
+}
+
+#[tonic::async_trait]
+pub trait VectorStore: Send + Sync {
+    async fn collection_exists(&self, collection: &str) -> Result<bool, EmbeddingError>;
+
+    async fn list_collections(&self) -> Result<Vec<String>, EmbeddingError>;
+
+    // Returns the collection an alias currently points at
+    async fn resolve_alias(&self, alias: &str) -> Result<Option<String>, EmbeddingError>;
+
+    // Points an alias at a collection without readers ever seeing it missing
+    async fn switch_alias(&self, alias: &str, collection: &str) -> Result<(), EmbeddingError>;
+
+    // Creates the collection with the given vectors and its payload indexes if it doesn't
+    // exist yet, returning the vectors the collection actually stores
+    async fn ensure_collection(
+        &self,
+        config: &CollectionConfig,
+        vectors: &[VectorSpec],
+    ) -> Result<Vec<VectorSpec>, EmbeddingError>;
+
+    // Compares a live collection against its config, warning about or migrating any differences
+    async fn sync_collection(&self, config: &CollectionConfig) -> Result<(), EmbeddingError>;
+
+    async fn upsert_points(&self, collection: &str, points: Vec<VectorPoint>) -> Result<(), EmbeddingError>;
+
+    // Removes every chunk belonging to the given documents
+    async fn delete_documents(&self, collection: &str, documents: &[DocumentKey]) -> Result<(), EmbeddingError>;
+
+    async fn search(
+        &self,
+        collection: &str,
+        vector_name: Option<&str>,
+        vector: Vec<f32>,
+        scopes: Vec<SearchScope>,
+        limit: u64,
+        params: Option<SearchParams>,
+    ) -> Result<Vec<ScoredChunk>, EmbeddingError>;
+
+    // Point ids of up to `limit` chunks within the scopes
+    async fn chunk_ids(
+        &self,
+        collection: &str,
+        scopes: Vec<SearchScope>,
+        limit: u32,
+    ) -> Result<Vec<String>, EmbeddingError>;
+
+    async fn recommend(
+        &self,
+        collection: &str,
+        vector_name: Option<&str>,
+        recommendation: Recommendation,
+        scopes: Vec<SearchScope>,
+        limit: u64,
+        params: Option<SearchParams>,
+    ) -> Result<Vec<ScoredChunk>, EmbeddingError>;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VectorStoreBackend {
+    Qdrant,
+    Pgvector,
+}
+
+impl VectorStoreBackend {
+    pub fn from_env() -> Self {
+        match std::env::var("VECTOR_STORE")
+            .unwrap_or_default()
+            .to_lowercase()
+            .as_str()
+        {
+            "pgvector" | "postgres" => VectorStoreBackend::Pgvector,
+            "" | "qdrant" => VectorStoreBackend::Qdrant,
+            other => {
+                log::warn!("Unknown VECTOR_STORE `{}`, falling back to Qdrant", other);
+                VectorStoreBackend::Qdrant
+            }
+        }
+    }
+}
@@ -0,0 +1,682 @@
+use qdrant_client::{
+    qdrant::{
+        point_id::PointIdOptions, points_selector::PointsSelectorOneOf, quantization_config,
+        quantization_config_diff,
+        vectors_config::Config, vectors_config_diff, BinaryQuantization, CollectionParamsDiff,
+        Condition,
+        CreateAlias, CreateCollection, CreateFieldIndexCollectionBuilder, DeletePoints, Distance, FieldType,
+        Filter, HnswConfigDiff, OptimizersConfigDiff, PayloadSchemaType, PointId, PointStruct,
+        PointsSelector, QuantizationConfig, QuantizationConfigDiff, QuantizationType,
+        RecommendPoints, ScalarQuantization, ScoredPoint, ScrollPoints, SearchParams,
+        SearchPoints, UpdateCollection, UpsertPoints,
+        VectorParams, VectorParamsDiff, VectorParamsDiffMap, VectorParamsMap, VectorsConfig,
+        VectorsConfigDiff,
+    },
+    Payload, Qdrant,
+};
+
+use std::collections::HashMap;
+
+use super::{
+    document_scopes, Recommendation, ScoredChunk, SearchScope, StorePayload, VectorPoint,
+    VectorSpec, VectorStore,
+};
+use crate::config::{
+    CollectionConfig, DistanceMetric, HnswConfig, PayloadIndexType, QuantizationKind,
+    QuantizationSettings,
+};
+use crate::embed::document_text::DocumentKey;
+use crate::embed::errors::{EmbeddingError, QdrantClientError};
+
+pub struct QdrantStore {
+    client: &'static Qdrant,
+}
+
+impl QdrantStore {
+    pub fn new(client: &'static Qdrant) -> Self {
+        QdrantStore { client }
+    }
+
+    async fn vector_specs(&self, collection: &str) -> Result<Vec<VectorSpec>, EmbeddingError> {
+        let vectors_config = self
+            .client
+            .collection_info(collection)
+            .await
+            .map_err(QdrantClientError::from)?
+            .result
+            .and_then(|info| info.config)
+            .and_then(|config| config.params)
+            .and_then(|params| params.vectors_config)
+            .and_then(|vectors| vectors.config);
+
+        Ok(named_vector_params(vectors_config)
+            .into_iter()
+            .map(|(name, params)| VectorSpec {
+                name,
+                dimension: params.size,
+            })
+            .collect())
+    }
+}
+
+// Flattens both vector layouts; unnamed collections have a single `None` entry
+fn named_vector_params(config: Option<Config>) -> Vec<(Option<String>, VectorParams)> {
+    match config {
+        Some(Config::Params(params)) => vec![(None, params)],
+        Some(Config::ParamsMap(params_map)) => params_map
+            .map
+            .into_iter()
+            .map(|(name, params)| (Some(name), params))
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+fn vector_params(config: &CollectionConfig, dimension: u64) -> VectorParams {
+    VectorParams {
+        size: dimension,
+        on_disk: Some(config.on_disk),
+        distance: Distance::from(config.distance).into(),
+        ..Default::default()
+    }
+}
+
+impl From<DistanceMetric> for Distance {
+    fn from(distance: DistanceMetric) -> Self {
+        match distance {
+            DistanceMetric::Cosine => Distance::Cosine,
+            DistanceMetric::Dot => Distance::Dot,
+            DistanceMetric::Euclid => Distance::Euclid,
+            DistanceMetric::Manhattan => Distance::Manhattan,
+        }
+    }
+}
+
+impl From<PayloadIndexType> for FieldType {
+    fn from(index_type: PayloadIndexType) -> Self {
+        match index_type {
+            PayloadIndexType::Keyword => FieldType::Keyword,
+            PayloadIndexType::Integer => FieldType::Integer,
+            PayloadIndexType::Float => FieldType::Float,
+            PayloadIndexType::Bool => FieldType::Bool,
+            PayloadIndexType::Text => FieldType::Text,
+            PayloadIndexType::Datetime => FieldType::Datetime,
+        }
+    }
+}
+
+impl From<PayloadIndexType> for PayloadSchemaType {
+    fn from(index_type: PayloadIndexType) -> Self {
+        match index_type {
+            PayloadIndexType::Keyword => PayloadSchemaType::Keyword,
+            PayloadIndexType::Integer => PayloadSchemaType::Integer,
+            PayloadIndexType::Float => PayloadSchemaType::Float,
+            PayloadIndexType::Bool => PayloadSchemaType::Bool,
+            PayloadIndexType::Text => PayloadSchemaType::Text,
+            PayloadIndexType::Datetime => PayloadSchemaType::Datetime,
+        }
+    }
+}
+
+fn hnsw_config_diff(hnsw: HnswConfig) -> HnswConfigDiff {
+    HnswConfigDiff {
+        m: hnsw.m,
+        ef_construct: hnsw.ef_construct,
+        ..Default::default()
+    }
+}
+
+fn scalar_quantization(settings: QuantizationSettings) -> ScalarQuantization {
+    ScalarQuantization {
+        r#type: QuantizationType::Int8.into(),
+        quantile: settings.quantile,
+        always_ram: settings.always_ram,
+    }
+}
+
+fn binary_quantization(settings: QuantizationSettings) -> BinaryQuantization {
+    BinaryQuantization {
+        always_ram: settings.always_ram,
+    }
+}
+
+fn quantization_config(settings: QuantizationSettings) -> QuantizationConfig {
+    QuantizationConfig {
+        quantization: Some(match settings.kind {
+            QuantizationKind::Scalar => {
+                quantization_config::Quantization::Scalar(scalar_quantization(settings))
+            }
+            QuantizationKind::Binary => {
+                quantization_config::Quantization::Binary(binary_quantization(settings))
+            }
+        }),
+    }
+}
+
+fn quantization_config_diff(settings: Option<QuantizationSettings>) -> QuantizationConfigDiff {
+    QuantizationConfigDiff {
+        quantization: Some(match settings {
+            Some(settings) if settings.kind == QuantizationKind::Scalar => {
+                quantization_config_diff::Quantization::Scalar(scalar_quantization(settings))
+            }
+            Some(settings) => {
+                quantization_config_diff::Quantization::Binary(binary_quantization(settings))
+            }
+            None => quantization_config_diff::Quantization::Disabled(Default::default()),
+        }),
+    }
+}
+
+fn quantization_matches(
+    live: Option<&QuantizationConfig>,
+    settings: Option<QuantizationSettings>,
+) -> bool {
+    let live = live.and_then(|config| config.quantization.as_ref());
+    let always_ram_matches = |live_always_ram: Option<bool>, always_ram: Option<bool>| {
+        always_ram.is_none() || live_always_ram.unwrap_or(false) == always_ram.unwrap_or(false)
+    };
+
+    match (live, settings) {
+        (None, None) => true,
+        (Some(quantization_config::Quantization::Scalar(live)), Some(settings))
+            if settings.kind == QuantizationKind::Scalar =>
+        {
+            (settings.quantile.is_none() || live.quantile == settings.quantile)
+                && always_ram_matches(live.always_ram, settings.always_ram)
+        }
+        (Some(quantization_config::Quantization::Binary(live)), Some(settings))
+            if settings.kind == QuantizationKind::Binary =>
+        {
+            always_ram_matches(live.always_ram, settings.always_ram)
+        }
+        _ => false,
+    }
+}
+
+fn scope_to_filter(scope: SearchScope) -> Filter {
+    let mut conditions = vec![Condition::matches("table_name", scope.table_name)];
+    if let Some(user_id) = scope.user_id {
+        conditions.push(Condition::matches("user_id", user_id));
+    }
+    if !scope.document_ids.is_empty() {
+        conditions.push(Condition::matches("document_id", scope.document_ids));
+    }
+    if !scope.languages.is_empty() {
+        conditions.push(Condition::matches("language", scope.languages));
+    }
+
+    Filter::must(conditions)
+}
+
+// Chunks in any of the scopes
+fn scopes_to_filter(scopes: Vec<SearchScope>) -> Filter {
+    Filter::should(
+        scopes
+            .into_iter()
+            .map(|scope| scope_to_filter(scope).into())
+            .collect::<Vec<_>>(),
+    )
+}
+
+fn scored_chunk(scored_point: ScoredPoint) -> ScoredChunk {
+    ScoredChunk {
+        score: scored_point.score,
+        payload: scored_point
+            .payload
+            .into_iter()
+            .map(|(key, value)| (key, value.into_json()))
+            .collect::<StorePayload>(),
+    }
+}
+
+// Points are stored with UUIDs, but Qdrant allows numbers too
+fn point_id_string(id: PointId) -> Option<String> {
+    match id.point_id_options? {
+        PointIdOptions::Uuid(uuid) => Some(uuid),
+        PointIdOptions::Num(num) => Some(num.to_string()),
+    }
+}
+
+#[tonic::async_trait]
+impl VectorStore for QdrantStore {
+    async fn collection_exists(&self, collection: &str) -> Result<bool, EmbeddingError> {
+        Ok(self
+            .client
+            .collection_exists(collection)
+            .await
+            .map_err(QdrantClientError::from)?)
+    }
+
+    async fn list_collections(&self) -> Result<Vec<String>, EmbeddingError> {
+        Ok(self
+            .client
+            .list_collections()
+            .await
+            .map_err(QdrantClientError::from)?
+            .collections
+            .into_iter()
+            .map(|collection| collection.name)
+            .collect())
+    }
+
+    async fn resolve_alias(&self, alias: &str) -> Result<Option<String>, EmbeddingError> {
+        Ok(self
+            .client
+            .list_aliases()
+            .await
+            .map_err(QdrantClientError::from)?
+            .aliases
+            .into_iter()
+            .find(|description| description.alias_name == alias)
+            .map(|description| description.collection_name))
+    }
+
+    async fn switch_alias(&self, alias: &str, collection: &str) -> Result<(), EmbeddingError> {
+        // Qdrant replaces an existing alias of the same name in one operation
+        self.client
+            .create_alias(CreateAlias {
+                collection_name: collection.to_string(),
+                alias_name: alias.to_string(),
+            })
+            .await
+            .map_err(QdrantClientError::from)?;
+
+        Ok(())
+    }
+
+    async fn ensure_collection(
+        &self,
+        config: &CollectionConfig,
+        vectors: &[VectorSpec],
+    ) -> Result<Vec<VectorSpec>, EmbeddingError> {
+        if self.collection_exists(&config.name).await? {
+            return self.vector_specs(&config.name).await;
+        }
+
+        let vectors_config = match vectors {
+            [VectorSpec {
+                name: None,
+                dimension,
+            }] => Config::Params(vector_params(config, *dimension)),
+            _ => Config::ParamsMap(VectorParamsMap {
+                map: vectors
+                    .iter()
+                    .map(|spec| {
+                        (
+                            spec.name.clone().unwrap_or_default(),
+                            vector_params(config, spec.dimension),
+                        )
+                    })
+                    .collect(),
+            }),
+        };
+
+        let create_collection_result = self
+            .client
+            .create_collection(CreateCollection {
+                collection_name: config.name.clone(),
+                vectors_config: Some(VectorsConfig {
+                    config: Some(vectors_config),
+                }),
+                hnsw_config: config.hnsw.map(hnsw_config_diff),
+                optimizers_config: Some(OptimizersConfigDiff {
+                    memmap_threshold: config.memmap_threshold,
+                    indexing_threshold: config.indexing_threshold,
+                    ..Default::default()
+                }),
+                quantization_config: config.quantization.map(quantization_config),
+                shard_number: config.shard_number,
+                replication_factor: config.replication_factor,
+                ..Default::default()
+            })
+            .await
+            .map_err(QdrantClientError::from);
+
+        // Handle specific error for existing collection
+        if let Err(e) = create_collection_result {
+            match e {
+                QdrantClientError::Rejected(ref msg) if msg.contains("already exists") => {
+                    // Ignore the error and continue if the collection already exists
+                }
+                _ => return Err(EmbeddingError::from(e)), // Propagate other errors
+            }
+        }
+
+        // Create indexes
+        for index in &config.payload_indexes {
+            self.client
+                .create_field_index(
+                    CreateFieldIndexCollectionBuilder::new(
+                        config.name.as_str(),
+                        index.field.as_str(),
+                        FieldType::from(index.index_type),
+                    )
+                    .wait(true),
+                )
+                .await
+                .map_err(QdrantClientError::from)?;
+        }
+
+        Ok(vectors.to_vec())
+    }
+
+    async fn sync_collection(&self, config: &CollectionConfig) -> Result<(), EmbeddingError> {
+        if !self.collection_exists(&config.name).await? {
+            log::info!(
+                "Collection {} doesn't exist yet, it will be created on first ingest",
+                config.name
+            );
+            return Ok(());
+        }
+
+        let info = self
+            .client
+            .collection_info(config.name.as_str())
+            .await
+            .map_err(QdrantClientError::from)?
+            .result
+            .unwrap_or_default();
+        let live = info.config.clone().unwrap_or_default();
+        let params = live.params.clone().unwrap_or_default();
+        let live_vectors =
+            named_vector_params(params.vectors_config.clone().and_then(|vectors| vectors.config));
+
+        // These can only change by recreating the collection
+        let mut fixed_differences = Vec::new();
+        for (name, vector_params) in &live_vectors {
+            if vector_params.distance != i32::from(Distance::from(config.distance)) {
+                fixed_differences.push(format!(
+                    "{}distance is {:?}, config wants {:?}",
+                    name.as_ref().map(|name| format!("vector {} ", name)).unwrap_or_default(),
+                    Distance::try_from(vector_params.distance).unwrap_or(Distance::UnknownDistance),
+                    config.distance
+                ));
+            }
+        }
+        if let Some(shard_number) = config.shard_number {
+            if params.shard_number != shard_number {
+                fixed_differences.push(format!(
+                    "shard_number is {}, config wants {}",
+                    params.shard_number, shard_number
+                ));
+            }
+        }
+        for difference in &fixed_differences {
+            log::warn!(
+                "Collection {} {}; this requires recreating the collection",
+                config.name,
+                difference
+            );
+        }
+
+        let mut update = UpdateCollection {
+            collection_name: config.name.clone(),
+            ..Default::default()
+        };
+        let mut differences = Vec::new();
+
+        if live_vectors
+            .iter()
+            .any(|(_, vector_params)| vector_params.on_disk.unwrap_or(false) != config.on_disk)
+        {
+            differences.push(format!("on_disk -> {}", config.on_disk));
+            let on_disk_diff = || VectorParamsDiff {
+                on_disk: Some(config.on_disk),
+                ..Default::default()
+            };
+            update.vectors_config = Some(VectorsConfigDiff {
+                config: Some(match live_vectors.as_slice() {
+                    [(None, _)] => vectors_config_diff::Config::Params(on_disk_diff()),
+                    _ => vectors_config_diff::Config::ParamsMap(VectorParamsDiffMap {
+                        map: live_vectors
+                            .iter()
+                            .filter_map(|(name, _)| name.clone())
+                            .map(|name| (name, on_disk_diff()))
+                            .collect(),
+                    }),
+                }),
+            });
+        }
+
+        if let Some(hnsw) = config.hnsw {
+            let live_hnsw = live.hnsw_config.unwrap_or_default();
+            if (hnsw.m.is_some() && hnsw.m != live_hnsw.m)
+                || (hnsw.ef_construct.is_some() && hnsw.ef_construct != live_hnsw.ef_construct)
+            {
+                differences.push(format!("hnsw -> {:?}", hnsw));
+                update.hnsw_config = Some(hnsw_config_diff(hnsw));
+            }
+        }
+
+        let live_optimizers = live.optimizer_config.unwrap_or_default();
+        if (config.memmap_threshold.is_some()
+            && config.memmap_threshold != live_optimizers.memmap_threshold)
+            || (config.indexing_threshold.is_some()
+                && config.indexing_threshold != live_optimizers.indexing_threshold)
+        {
+            differences.push(format!(
+                "memmap_threshold -> {:?}, indexing_threshold -> {:?}",
+                config.memmap_threshold, config.indexing_threshold
+            ));
+            update.optimizers_config = Some(OptimizersConfigDiff {
+                memmap_threshold: config.memmap_threshold,
+                indexing_threshold: config.indexing_threshold,
+                ..Default::default()
+            });
+        }
+
+        if let Some(replication_factor) = config.replication_factor {
+            if params.replication_factor != Some(replication_factor) {
+                differences.push(format!("replication_factor -> {}", replication_factor));
+                update.params = Some(CollectionParamsDiff {
+                    replication_factor: Some(replication_factor),
+                    ..Default::default()
+                });
+            }
+        }
+
+        if !quantization_matches(live.quantization_config.as_ref(), config.quantization) {
+            differences.push(format!("quantization -> {:?}", config.quantization));
+            update.quantization_config = Some(quantization_config_diff(config.quantization));
+        }
+
+        if !differences.is_empty() {
+            if config.migrate {
+                log::info!(
+                    "Migrating collection {}: {}",
+                    config.name,
+                    differences.join(", ")
+                );
+                self.client
+                    .update_collection(update)
+                    .await
+                    .map_err(QdrantClientError::from)?;
+            } else {
+                log::warn!(
+                    "Collection {} differs from its config ({}); set `migrate = true` to apply",
+                    config.name,
+                    differences.join(", ")
+                );
+            }
+        }
+
+        // Missing payload indexes are always safe to add
+        for index in &config.payload_indexes {
+            let expected_type = i32::from(PayloadSchemaType::from(index.index_type));
+            match info.payload_schema.get(&index.field) {
+                Some(schema) if schema.data_type == expected_type => {}
+                Some(_) => log::warn!(
+                    "Payload index {} on collection {} doesn't have type {:?}",
+                    index.field,
+                    config.name,
+                    index.index_type
+                ),
+                None => {
+                    log::info!(
+                        "Creating payload index {} on collection {}",
+                        index.field,
+                        config.name
+                    );
+                    self.client
+                        .create_field_index(
+                            CreateFieldIndexCollectionBuilder::new(
+                                config.name.as_str(),
+                                index.field.as_str(),
+                                FieldType::from(index.index_type),
+                            )
+                            .wait(true),
+                        )
+                        .await
+                        .map_err(QdrantClientError::from)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn upsert_points(&self, collection: &str, points: Vec<VectorPoint>) -> Result<(), EmbeddingError> {
+        let points = points
+            .into_iter()
+            .map(|point| {
+                let json_payload = serde_json::to_value(point.payload).unwrap();
+                let payload: Payload = json_payload.try_into().unwrap();
+
+                let mut vectors = point.vectors;
+                match vectors.remove(&None) {
+                    Some(vector) => PointStruct::new(point.id, vector, payload),
+                    None => PointStruct::new(
+                        point.id,
+                        vectors
+                            .into_iter()
+                            .filter_map(|(name, vector)| Some((name?, vector)))
+                            .collect::<HashMap<String, Vec<f32>>>(),
+                        payload,
+                    ),
+                }
+            })
+            .collect::<Vec<PointStruct>>();
+
+        self.client
+            .upsert_points(UpsertPoints {
+                collection_name: collection.to_string(),
+                points,
+                ..Default::default()
+            })
+            .await
+            .map_err(QdrantClientError::from)?;
+
+        Ok(())
+    }
+
+    async fn delete_documents(&self, collection: &str, documents: &[DocumentKey]) -> Result<(), EmbeddingError> {
+        if documents.is_empty() {
+            return Ok(());
+        }
+
+        self.client
+            .delete_points(DeletePoints {
+                collection_name: collection.to_string(),
+                points: Some(PointsSelector {
+                    points_selector_one_of: Some(PointsSelectorOneOf::Filter(scopes_to_filter(
+                        document_scopes(documents),
+                    ))),
+                }),
+                ..Default::default()
+            })
+            .await
+            .map_err(QdrantClientError::from)?;
+
+        Ok(())
+    }
+
+    async fn search(
+        &self,
+        collection: &str,
+        vector_name: Option<&str>,
+        vector: Vec<f32>,
+        scopes: Vec<SearchScope>,
+        limit: u64,
+        params: Option<SearchParams>,
+    ) -> Result<Vec<ScoredChunk>, EmbeddingError> {
+        let response = self
+            .client
+            .search_points(SearchPoints {
+                collection_name: collection.to_string(),
+                vector,
+                vector_name: vector_name.map(str::to_string),
+                limit,
+                with_payload: Some(true.into()),
+                with_vectors: Some(false.into()),
+                filter: Some(scopes_to_filter(scopes)),
+                params,
+                ..Default::default()
+            })
+            .await
+            .map_err(QdrantClientError::from)?;
+
+        Ok(response.result.into_iter().map(scored_chunk).collect())
+    }
+
+    async fn chunk_ids(
+        &self,
+        collection: &str,
+        scopes: Vec<SearchScope>,
+        limit: u32,
+    ) -> Result<Vec<String>, EmbeddingError> {
+        let response = self
+            .client
+            .scroll(ScrollPoints {
+                collection_name: collection.to_string(),
+                filter: Some(scopes_to_filter(scopes)),
+                limit: Some(limit),
+                with_payload: Some(false.into()),
+                with_vectors: Some(false.into()),
+                ..Default::default()
+            })
+            .await
+            .map_err(QdrantClientError::from)?;
+
+        Ok(response
+            .result
+            .into_iter()
+            .filter_map(|point| point_id_string(point.id?))
+            .collect())
+    }
+
+    async fn recommend(
+        &self,
+        collection: &str,
+        vector_name: Option<&str>,
+        recommendation: Recommendation,
+        scopes: Vec<SearchScope>,
+        limit: u64,
+        params: Option<SearchParams>,
+    ) -> Result<Vec<ScoredChunk>, EmbeddingError> {
+        let mut filter = scopes_to_filter(scopes);
+        filter.must_not = recommendation
+            .excluded
+            .into_iter()
+            .map(|scope| scope_to_filter(scope).into())
+            .collect();
+
+        let response = self
+            .client
+            .recommend(RecommendPoints {
+                collection_name: collection.to_string(),
+                positive: recommendation.positive.into_iter().map(PointId::from).collect(),
+                negative: recommendation.negative.into_iter().map(PointId::from).collect(),
+                filter: Some(filter),
+                limit,
+                with_payload: Some(true.into()),
+                with_vectors: Some(false.into()),
+                params,
+                using: vector_name.map(str::to_string),
+                ..Default::default()
+            })
+            .await
+            .map_err(QdrantClientError::from)?;
+
+        Ok(response.result.into_iter().map(scored_chunk).collect())
+    }
+}
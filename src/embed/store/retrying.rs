@@ -0,0 +1,120 @@
+use qdrant_client::qdrant::SearchParams;
+
+use super::{Recommendation, ScoredChunk, SearchScope, VectorPoint, VectorSpec, VectorStore};
+use crate::config::CollectionConfig;
+use crate::embed::document_text::DocumentKey;
+use crate::embed::errors::EmbeddingError;
+use crate::embed::retry::{with_retry, VECTOR_STORE_BREAKER};
+
+// Retries transient failures of any backend and fails fast while it's down.
+// Every store operation is idempotent, so repeating one is safe.
+pub struct RetryingStore {
+    inner: Box<dyn VectorStore>,
+}
+
+impl RetryingStore {
+    pub fn new(inner: Box<dyn VectorStore>) -> Self {
+        RetryingStore { inner }
+    }
+}
+
+#[tonic::async_trait]
+impl VectorStore for RetryingStore {
+    async fn collection_exists(&self, collection: &str) -> Result<bool, EmbeddingError> {
+        with_retry(&VECTOR_STORE_BREAKER, || self.inner.collection_exists(collection)).await
+    }
+
+    async fn list_collections(&self) -> Result<Vec<String>, EmbeddingError> {
+        with_retry(&VECTOR_STORE_BREAKER, || self.inner.list_collections()).await
+    }
+
+    async fn resolve_alias(&self, alias: &str) -> Result<Option<String>, EmbeddingError> {
+        with_retry(&VECTOR_STORE_BREAKER, || self.inner.resolve_alias(alias)).await
+    }
+
+    async fn switch_alias(&self, alias: &str, collection: &str) -> Result<(), EmbeddingError> {
+        with_retry(&VECTOR_STORE_BREAKER, || self.inner.switch_alias(alias, collection)).await
+    }
+
+    async fn ensure_collection(
+        &self,
+        config: &CollectionConfig,
+        vectors: &[VectorSpec],
+    ) -> Result<Vec<VectorSpec>, EmbeddingError> {
+        with_retry(&VECTOR_STORE_BREAKER, || self.inner.ensure_collection(config, vectors)).await
+    }
+
+    async fn sync_collection(&self, config: &CollectionConfig) -> Result<(), EmbeddingError> {
+        with_retry(&VECTOR_STORE_BREAKER, || self.inner.sync_collection(config)).await
+    }
+
+    async fn upsert_points(&self, collection: &str, points: Vec<VectorPoint>) -> Result<(), EmbeddingError> {
+        with_retry(&VECTOR_STORE_BREAKER, || {
+            self.inner.upsert_points(collection, points.clone())
+        })
+        .await
+    }
+
+    async fn delete_documents(&self, collection: &str, documents: &[DocumentKey]) -> Result<(), EmbeddingError> {
+        with_retry(&VECTOR_STORE_BREAKER, || {
+            self.inner.delete_documents(collection, documents)
+        })
+        .await
+    }
+
+    async fn search(
+        &self,
+        collection: &str,
+        vector_name: Option<&str>,
+        vector: Vec<f32>,
+        scopes: Vec<SearchScope>,
+        limit: u64,
+        params: Option<SearchParams>,
+    ) -> Result<Vec<ScoredChunk>, EmbeddingError> {
+        with_retry(&VECTOR_STORE_BREAKER, || {
+            self.inner.search(
+                collection,
+                vector_name,
+                vector.clone(),
+                scopes.clone(),
+                limit,
+                params,
+            )
+        })
+        .await
+    }
+
+    async fn chunk_ids(
+        &self,
+        collection: &str,
+        scopes: Vec<SearchScope>,
+        limit: u32,
+    ) -> Result<Vec<String>, EmbeddingError> {
+        with_retry(&VECTOR_STORE_BREAKER, || {
+            self.inner.chunk_ids(collection, scopes.clone(), limit)
+        })
+        .await
+    }
+
+    async fn recommend(
+        &self,
+        collection: &str,
+        vector_name: Option<&str>,
+        recommendation: Recommendation,
+        scopes: Vec<SearchScope>,
+        limit: u64,
+        params: Option<SearchParams>,
+    ) -> Result<Vec<ScoredChunk>, EmbeddingError> {
+        with_retry(&VECTOR_STORE_BREAKER, || {
+            self.inner.recommend(
+                collection,
+                vector_name,
+                recommendation.clone(),
+                scopes.clone(),
+                limit,
+                params,
+            )
+        })
+        .await
+    }
+}
@@ -0,0 +1,192 @@
+// Where the Markdown headings and paragraphs of a text are, so every chunk can say which
+// section it came from. Paragraphs are separated by blank lines. Text can be fed in
+// pieces, e.g. a window at a time, with offsets always into the full text.
+#[derive(Debug, Default)]
+pub struct TextStructure {
+    // Byte offset each paragraph starts at
+    paragraph_starts: Vec<u64>,
+    // Byte offset of each heading, with the path of headings leading to it
+    headings: Vec<(u64, Vec<String>)>,
+    open_headings: Vec<(usize, String)>,
+    offset: u64,
+    partial_line: String,
+    in_paragraph: bool,
+}
+
+// `## Title` is a level 2 heading titled "Title"
+fn markdown_heading(line: &str) -> Option<(usize, String)> {
+    let level = line.chars().take_while(|c| *c == '#').count();
+    if !(1..=6).contains(&level) {
+        return None;
+    }
+    let title = line[level..].strip_prefix(' ')?.trim().trim_end_matches('#').trim();
+
+    Some((level, title.to_string()))
+}
+
+impl TextStructure {
+    pub fn of(text: &str) -> Self {
+        let mut structure = TextStructure::default();
+        structure.feed(text);
+        structure.finish();
+        structure
+    }
+
+    pub fn feed(&mut self, text: &str) {
+        for piece in text.split_inclusive('\n') {
+            if !piece.ends_with('\n') {
+                self.partial_line.push_str(piece);
+            } else if self.partial_line.is_empty() {
+                self.line(piece);
+            } else {
+                let mut line = std::mem::take(&mut self.partial_line);
+                line.push_str(piece);
+                self.line(&line);
+            }
+        }
+    }
+
+    // Counts a last line without a newline
+    pub fn finish(&mut self) {
+        let line = std::mem::take(&mut self.partial_line);
+        if !line.is_empty() {
+            self.line(&line);
+        }
+    }
+
+    fn line(&mut self, line: &str) {
+        let start = self.offset;
+        self.offset += line.len() as u64;
+
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            self.in_paragraph = false;
+            return;
+        }
+
+        if let Some((level, title)) = markdown_heading(trimmed) {
+            self.open_headings.retain(|(open_level, _)| *open_level < level);
+            self.open_headings.push((level, title));
+            self.headings.push((
+                start,
+                self.open_headings.iter().map(|(_, title)| title.clone()).collect(),
+            ));
+            self.in_paragraph = false;
+            return;
+        }
+
+        if !self.in_paragraph {
+            self.paragraph_starts.push(start);
+            self.in_paragraph = true;
+        }
+    }
+
+    // Titles of the section the byte at `offset` is in, outermost first
+    pub fn heading_path(&self, offset: usize) -> &[String] {
+        let index = self
+            .headings
+            .partition_point(|(start, _)| *start <= offset as u64);
+        match index {
+            0 => &[],
+            index => &self.headings[index - 1].1,
+        }
+    }
+
+    // 0-based index of the paragraph the byte at `offset` is in, or the next one
+    pub fn paragraph_index(&self, offset: usize) -> usize {
+        self.paragraph_starts
+            .partition_point(|start| *start <= offset as u64)
+            .saturating_sub(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = "Intro paragraph,\nstill the intro.\n\n# Rust\n\nOwnership is checked.\n\n## Borrowing ##\nRéférences are borrowed.\n\nLifetimes too.\n# Zig\nComptime";
+
+    fn offset_of(needle: &str) -> usize {
+        TEXT.find(needle).unwrap()
+    }
+
+    // Every (heading_path, paragraph_index) of the text, by byte offset
+    fn layout(structure: &TextStructure) -> Vec<(Vec<String>, usize)> {
+        (0..=TEXT.len())
+            .map(|offset| {
+                (
+                    structure.heading_path(offset).to_vec(),
+                    structure.paragraph_index(offset),
+                )
+            })
+            .collect()
+    }
+
+    fn fed_in_pieces(piece_chars: usize) -> TextStructure {
+        let chars: Vec<char> = TEXT.chars().collect();
+        let mut structure = TextStructure::default();
+        for piece in chars.chunks(piece_chars) {
+            structure.feed(&piece.iter().collect::<String>());
+        }
+        structure.finish();
+        structure
+    }
+
+    #[test]
+    fn headings_and_paragraphs_are_found() {
+        let structure = TextStructure::of(TEXT);
+
+        assert!(structure.heading_path(offset_of("still")).is_empty());
+        assert_eq!(structure.heading_path(offset_of("Ownership")), ["Rust"]);
+        assert_eq!(
+            structure.heading_path(offset_of("Lifetimes")),
+            ["Rust", "Borrowing"]
+        );
+        assert_eq!(structure.heading_path(offset_of("Comptime")), ["Zig"]);
+
+        assert_eq!(structure.paragraph_index(offset_of("still")), 0);
+        assert_eq!(structure.paragraph_index(offset_of("Ownership")), 1);
+        assert_eq!(structure.paragraph_index(offset_of("férences")), 2);
+        assert_eq!(structure.paragraph_index(offset_of("Lifetimes")), 3);
+        assert_eq!(structure.paragraph_index(offset_of("Comptime")), 4);
+    }
+
+    #[test]
+    fn feeding_in_pieces_matches_feeding_the_text_whole() {
+        let whole = layout(&TextStructure::of(TEXT));
+
+        for piece_chars in [1, 2, 3, 5, 8, 13, 40] {
+            assert_eq!(
+                layout(&fed_in_pieces(piece_chars)),
+                whole,
+                "fed {} characters at a time",
+                piece_chars
+            );
+        }
+    }
+
+    #[test]
+    fn splitting_once_anywhere_matches_feeding_the_text_whole() {
+        let whole = layout(&TextStructure::of(TEXT));
+
+        for (split, _) in TEXT.char_indices() {
+            let mut structure = TextStructure::default();
+            structure.feed(&TEXT[..split]);
+            structure.feed(&TEXT[split..]);
+            structure.finish();
+
+            assert_eq!(layout(&structure), whole, "split at byte {}", split);
+        }
+    }
+
+    #[test]
+    fn markdown_heading_needs_a_space_and_at_most_six_hashes() {
+        assert_eq!(
+            markdown_heading("### Title ###"),
+            Some((3, "Title".to_string()))
+        );
+        assert_eq!(markdown_heading("#hashtag"), None);
+        assert_eq!(markdown_heading("####### Seven"), None);
+        assert_eq!(markdown_heading("Not # a heading"), None);
+    }
+}
@@ -0,0 +1,352 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, Utc};
+use sea_orm::{
+    sea_query::{Expr, OnConflict},
+    ActiveValue::{NotSet, Set},
+    ColumnTrait, EntityTrait, QueryFilter, Value,
+};
+use sha2::{Digest, Sha256};
+
+use super::{
+    document_text::{DocumentFrame, DocumentKey},
+    errors::EmbeddingError,
+    instances::get_db_instance,
+    tables::EmbeddableTable,
+};
+use crate::config::{get_config, EmbeddingProvider};
+use crate::entities::vecembed_sync_state::{ActiveModel, Column, Entity};
+use crate::grpc::server::vecembed_rpc::VectorDbDocument;
+
+// Alias of the sync state joined onto a table's rows
+pub const SYNC_STATE: &str = "sync_state";
+
+// What a document was last embedded from
+pub struct SyncedDocument<'a> {
+    pub document: &'a VectorDbDocument,
+    pub content_hash: String,
+    pub chunk_count: u32,
+    pub html_cleaned: bool,
+    // MinHash signature of the text, for finding near duplicates of it
+    pub minhash: Option<Vec<u8>>,
+    // Row of the same table the document nearly duplicates
+    pub duplicate_of: Option<i64>,
+    // When the text was read, before embedding it; a row updated since is stale again
+    pub read_at: DateTime<Utc>,
+}
+
+// The models every row is embedded with, for reading the sync state
+pub fn embedding_models() -> String {
+    get_config()
+        .embedding
+        .vector_providers()
+        .iter()
+        .map(|provider| provider.model.clone())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+// What every row is embedded with: each provider's model, document template and
+// dimension. Changing any of them, or adding a provider, makes rows stale
+pub fn embedding_fingerprint() -> String {
+    providers_fingerprint(&get_config().embedding.vector_providers())
+}
+
+fn providers_fingerprint(providers: &[EmbeddingProvider]) -> String {
+    let mut hasher = Sha256::new();
+    for provider in providers {
+        hasher.update(provider.model.as_bytes());
+        hasher.update([0]);
+        hasher.update(provider.document_template.as_bytes());
+        hasher.update([0]);
+        if let Some(dimension) = provider.dimension {
+            hasher.update(dimension.to_string().as_bytes());
+        }
+        hasher.update([0]);
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+// Hashes the text a document's embeddings are made from, template included, as it's read
+pub struct ContentHasher(Sha256);
+
+impl ContentHasher {
+    pub fn new(frame: Option<&DocumentFrame>) -> Self {
+        let mut hasher = Sha256::new();
+        if let Some(frame) = frame {
+            hasher.update(frame.before.as_bytes());
+            hasher.update([0]);
+            hasher.update(frame.after.as_bytes());
+            hasher.update([0]);
+        }
+        ContentHasher(hasher)
+    }
+
+    pub fn update(&mut self, text: &str) {
+        self.0.update(text.as_bytes());
+    }
+
+    pub fn finish(self) -> String {
+        format!("{:x}", self.0.finalize())
+    }
+}
+
+// `text` is the document's text as it's chunked, e.g. after its HTML is cleaned
+pub fn content_hash(text: &str, frame: Option<&DocumentFrame>) -> String {
+    let mut hasher = ContentHasher::new(frame);
+    hasher.update(text);
+    hasher.finish()
+}
+
+pub fn sync_state_join(table: &EmbeddableTable) -> String {
+    format!(
+        "LEFT JOIN vecembed_sync_state AS {state} ON {state}.table_name = '{table}' AND {state}.row_id = {table}.{id}",
+        state = SYNC_STATE,
+        table = table.name,
+        id = table.id_column
+    )
+}
+
+// Rows whose embeddings are missing, out of date, made with other settings or failed last time
+pub fn stale_rows_filter(table: &EmbeddableTable) -> (String, Vec<Value>) {
+    (
+        format!(
+            "({state}.synced_at IS NULL OR {state}.last_error IS NOT NULL OR {table}.{updated} IS NULL
+            OR {state}.synced_at < {table}.{updated} OR NOT ({state}.settings_fingerprint <=> ?))",
+            state = SYNC_STATE,
+            table = table.name,
+            updated = table.updated_at_column
+        ),
+        vec![embedding_fingerprint().into()],
+    )
+}
+
+// Finds the documents whose text and embedding settings haven't changed since they were last
+// embedded, and records them as up to date as of `read_at` without embedding them again
+pub async fn skip_unchanged(
+    hashes: &HashMap<DocumentKey, String>,
+    read_at: DateTime<Utc>,
+) -> Result<HashSet<DocumentKey>, EmbeddingError> {
+    let mut ids_by_table: HashMap<&str, Vec<i64>> = HashMap::new();
+    for (table_name, id) in hashes.keys() {
+        ids_by_table.entry(table_name.as_str()).or_default().push(*id);
+    }
+
+    let db = get_db_instance().await;
+    let fingerprint = embedding_fingerprint();
+    let mut unchanged = HashSet::new();
+
+    for (table_name, ids) in ids_by_table {
+        let unchanged_ids = Entity::find()
+            .filter(Column::TableName.eq(table_name))
+            .filter(Column::RowId.is_in(ids))
+            .all(db)
+            .await?
+            .into_iter()
+            .filter(|state| {
+                state.last_error.is_none()
+                    && state.settings_fingerprint.as_deref() == Some(fingerprint.as_str())
+                    && state.content_hash.as_ref()
+                        == hashes.get(&(table_name.to_string(), state.row_id))
+            })
+            .map(|state| state.row_id)
+            .collect::<Vec<_>>();
+        if unchanged_ids.is_empty() {
+            continue;
+        }
+
+        Entity::update_many()
+            .col_expr(Column::SyncedAt, Expr::value(read_at))
+            .filter(Column::TableName.eq(table_name))
+            .filter(Column::RowId.is_in(unchanged_ids.clone()))
+            .exec(db)
+            .await?;
+
+        unchanged.extend(unchanged_ids.into_iter().map(|id| (table_name.to_string(), id)));
+    }
+
+    Ok(unchanged)
+}
+
+pub async fn mark_synced(documents: Vec<SyncedDocument<'_>>) -> Result<(), EmbeddingError> {
+    if documents.is_empty() {
+        return Ok(());
+    }
+
+    let db = get_db_instance().await;
+    let models = embedding_models();
+    let fingerprint = embedding_fingerprint();
+    let states = documents.into_iter().map(|synced| ActiveModel {
+        id: NotSet,
+        table_name: Set(synced.document.table_name.clone()),
+        row_id: Set(synced.document.id),
+        content_hash: Set(Some(synced.content_hash)),
+        model: Set(Some(models.clone())),
+        settings_fingerprint: Set(Some(fingerprint.clone())),
+        synced_at: Set(Some(synced.read_at)),
+        chunk_count: Set(synced.chunk_count),
+        html_cleaned: Set(synced.html_cleaned),
+        minhash: Set(synced.minhash),
+        duplicate_of: Set(synced.duplicate_of),
+        last_error: Set(None),
+    });
+
+    Entity::insert_many(states)
+        .on_conflict(
+            OnConflict::columns([Column::TableName, Column::RowId])
+                .update_columns([
+                    Column::ContentHash,
+                    Column::Model,
+                    Column::SettingsFingerprint,
+                    Column::SyncedAt,
+                    Column::ChunkCount,
+                    Column::HtmlCleaned,
+                    Column::Minhash,
+                    Column::DuplicateOf,
+                    Column::LastError,
+                ])
+                .to_owned(),
+        )
+        .exec(db)
+        .await?;
+
+    Ok(())
+}
+
+// Keeps the last successful sync, so only the error changes
+pub async fn record_failure(
+    documents: &[VectorDbDocument],
+    error: &EmbeddingError,
+) -> Result<(), EmbeddingError> {
+    if documents.is_empty() {
+        return Ok(());
+    }
+
+    let db = get_db_instance().await;
+    let states = documents.iter().map(|document| ActiveModel {
+        id: NotSet,
+        table_name: Set(document.table_name.clone()),
+        row_id: Set(document.id),
+        content_hash: NotSet,
+        model: NotSet,
+        settings_fingerprint: NotSet,
+        synced_at: NotSet,
+        chunk_count: NotSet,
+        html_cleaned: NotSet,
+        minhash: NotSet,
+        duplicate_of: NotSet,
+        last_error: Set(Some(error.to_string())),
+    });
+
+    Entity::insert_many(states)
+        .on_conflict(
+            OnConflict::columns([Column::TableName, Column::RowId])
+                .update_column(Column::LastError)
+                .to_owned(),
+        )
+        .exec(db)
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(before: &str, after: &str) -> DocumentFrame {
+        DocumentFrame {
+            before: before.to_string(),
+            after: after.to_string(),
+            token_count: 0,
+        }
+    }
+
+    #[test]
+    fn stale_rows_filter_compares_sync_time_with_the_tables_updated_at_column() {
+        let (clause, values) = stale_rows_filter(&EmbeddableTable {
+            updated_at_column: "modified_at",
+            ..EmbeddableTable::test("posts")
+        });
+
+        assert!(clause.contains("posts.modified_at IS NULL"));
+        assert!(clause.contains("sync_state.synced_at < posts.modified_at"));
+        assert!(!clause.contains("updated_at"));
+        assert!(clause.contains("sync_state.last_error IS NOT NULL"));
+        assert!(clause.contains("NOT (sync_state.settings_fingerprint <=> ?)"));
+        assert_eq!(values, vec![Value::from(embedding_fingerprint())]);
+    }
+
+    #[test]
+    fn embedding_models_keeps_the_model_names_readable() {
+        let providers = get_config().embedding.vector_providers();
+
+        assert_eq!(embedding_models().split(',').count(), providers.len());
+        assert!(embedding_models().starts_with(&providers[0].model));
+    }
+
+    #[test]
+    fn changing_a_providers_document_template_makes_rows_stale() {
+        let (_, values) = stale_rows_filter(&EmbeddableTable::test("posts"));
+        let mut providers = get_config().embedding.vector_providers();
+        assert_eq!(values, vec![Value::from(providers_fingerprint(&providers))]);
+
+        providers[0].document_template = "passage: {text}".to_string();
+
+        assert_ne!(values, vec![Value::from(providers_fingerprint(&providers))]);
+    }
+
+    #[test]
+    fn providers_fingerprint_changes_with_the_model_and_dimension() {
+        let providers = get_config().embedding.vector_providers();
+        let fingerprint = providers_fingerprint(&providers);
+
+        let mut renamed = providers.clone();
+        renamed[0].model = "other-model".to_string();
+        let mut truncated = providers.clone();
+        truncated[0].dimension = Some(256);
+
+        assert_ne!(fingerprint, providers_fingerprint(&renamed));
+        assert_ne!(fingerprint, providers_fingerprint(&truncated));
+        assert_eq!(fingerprint, providers_fingerprint(&providers));
+    }
+
+    #[test]
+    fn content_hash_changes_with_the_template_frame() {
+        let text = "Rust is a systems programming language.";
+        let plain = content_hash(text, None);
+        let framed = content_hash(text, Some(&frame("Title\n\n", "")));
+
+        assert_ne!(plain, framed);
+        assert_ne!(
+            framed,
+            content_hash(text, Some(&frame("New title\n\n", "")))
+        );
+        assert_eq!(framed, content_hash(text, Some(&frame("Title\n\n", ""))));
+    }
+
+    #[test]
+    fn content_hash_tells_the_frame_apart_from_the_text() {
+        assert_ne!(
+            content_hash("b", Some(&frame("a", ""))),
+            content_hash("ab", Some(&frame("", "")))
+        );
+        assert_ne!(
+            content_hash("", Some(&frame("a", "b"))),
+            content_hash("", Some(&frame("ab", "")))
+        );
+    }
+
+    #[test]
+    fn content_hasher_matches_content_hash_when_fed_in_pieces() {
+        let frame = frame("Title\n\n", "\n\nFooter");
+        let mut hasher = ContentHasher::new(Some(&frame));
+        hasher.update("Rust is a ");
+        hasher.update("systems programming language.");
+
+        assert_eq!(
+            hasher.finish(),
+            content_hash("Rust is a systems programming language.", Some(&frame))
+        );
+    }
+}
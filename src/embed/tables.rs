@@ -0,0 +1,265 @@
+// Who a table's documents are retrieved for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Visibility {
+    // Every user, e.g. crawled web content
+    Public,
+    // Only the user in the table's owner column
+    Owner,
+}
+
+// What happens to a row whose text nearly matches an earlier row of the same table
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DuplicateHandling {
+    // Embedded, recording the earlier row as its canonical one so retrieval can collapse them
+    Mark,
+    // Not embedded at all
+    Skip,
+}
+
+// A table joined in for its columns, one row per embedded row
+#[derive(Debug)]
+pub struct TableRelation {
+    pub table: &'static str,
+    // Column of the embeddable table pointing at the relation
+    pub foreign_key: &'static str,
+    pub key: &'static str,
+}
+
+// Where a row's file is, for tables whose text VecEmbed extracts itself
+#[derive(Debug)]
+pub struct FileColumns {
+    // Path relative to FILE_STORAGE_PATH or FILE_STORAGE_URL
+    pub path_column: &'static str,
+    // Without one, the type is told from the file's extension and contents
+    pub mime_column: Option<&'static str>,
+}
+
+// Everything VecEmbed needs to know about an embeddable table. Import, sync tracking,
+// retrieval filters and the CLI all work from this, so supporting a table only takes
+// an entry in EMBEDDABLE_TABLES. Sync state is kept in `vecembed_sync_state`, so the
+// table itself isn't written to.
+#[derive(Debug)]
+pub struct EmbeddableTable {
+    pub name: &'static str,
+    pub id_column: &'static str,
+    // Column with the id of the user the row belongs to
+    pub owner_column: Option<&'static str>,
+    pub text_column: &'static str,
+    // Text that's embedded for each chunk of the text column, e.g. "{title}\n\n{body}".
+    // `{column}` and `{relation.column}` are filled from the row and its relations.
+    pub text_template: Option<&'static str>,
+    pub relations: &'static [TableRelation],
+    // Extract the text from the row's file rather than read the text column, which is
+    // only used for files of unsupported types
+    pub file: Option<FileColumns>,
+    // The text column holds HTML, e.g. crawled pages, whose menus, scripts and banners
+    // are left out of the embedded text; chunk offsets still point into the HTML
+    pub clean_html: bool,
+    // Look for near duplicates of each row; None embeds every row as it is
+    pub duplicates: Option<DuplicateHandling>,
+    // Rows updated after they were last embedded are stale
+    pub updated_at_column: &'static str,
+    // Rows with this column set are soft-deleted and never imported
+    pub deleted_at_column: Option<&'static str>,
+    pub visibility: Visibility,
+    // Searched when a request's `filter_ids` names no table; otherwise only when named
+    pub searched_by_default: bool,
+}
+
+pub const EMBEDDABLE_TABLES: &[EmbeddableTable] = &[
+    EmbeddableTable {
+        name: "contents",
+        id_column: "id",
+        owner_column: None,
+        text_column: "body",
+        text_template: Some("{page_title}\n{url}\n{content_sources.name}\n\n{body}"),
+        relations: &[TableRelation {
+            table: "content_sources",
+            foreign_key: "content_source_id",
+            key: "id",
+        }],
+        file: None,
+        clean_html: true,
+        duplicates: Some(DuplicateHandling::Mark),
+        updated_at_column: "updated_at",
+        deleted_at_column: Some("deleted_at"),
+        visibility: Visibility::Public,
+        searched_by_default: true,
+    },
+    EmbeddableTable {
+        name: "uploaded_files",
+        id_column: "id",
+        owner_column: Some("user_id"),
+        text_column: "text",
+        text_template: None,
+        relations: &[],
+        file: Some(FileColumns {
+            path_column: "path",
+            mime_column: None,
+        }),
+        clean_html: false,
+        duplicates: None,
+        updated_at_column: "updated_at",
+        deleted_at_column: Some("deleted_at"),
+        visibility: Visibility::Owner,
+        searched_by_default: true,
+    },
+    EmbeddableTable {
+        name: "chat_messages",
+        id_column: "id",
+        owner_column: Some("user_id"),
+        text_column: "message",
+        text_template: Some("{role}: {message}"),
+        relations: &[],
+        file: None,
+        clean_html: false,
+        duplicates: None,
+        updated_at_column: "updated_at",
+        deleted_at_column: Some("deleted_at"),
+        visibility: Visibility::Owner,
+        searched_by_default: false,
+    },
+    EmbeddableTable {
+        name: "generated_texts",
+        id_column: "id",
+        owner_column: Some("user_id"),
+        text_column: "text",
+        text_template: None,
+        relations: &[],
+        file: None,
+        clean_html: false,
+        duplicates: None,
+        updated_at_column: "updated_at",
+        deleted_at_column: Some("deleted_at"),
+        visibility: Visibility::Owner,
+        searched_by_default: false,
+    },
+    EmbeddableTable {
+        name: "podcast_episodes",
+        id_column: "id",
+        owner_column: Some("user_id"),
+        text_column: "content",
+        text_template: Some("{title}\n{description}\n\n{content}"),
+        relations: &[],
+        file: None,
+        clean_html: false,
+        duplicates: None,
+        updated_at_column: "updated_at",
+        deleted_at_column: None,
+        visibility: Visibility::Owner,
+        searched_by_default: false,
+    },
+    EmbeddableTable {
+        name: "user_queries",
+        id_column: "id",
+        owner_column: Some("user_id"),
+        text_column: "text",
+        text_template: None,
+        relations: &[],
+        file: None,
+        clean_html: false,
+        duplicates: None,
+        updated_at_column: "updated_at",
+        deleted_at_column: Some("deleted_at"),
+        visibility: Visibility::Owner,
+        searched_by_default: false,
+    },
+];
+
+pub fn embeddable_table(name: &str) -> Option<&'static EmbeddableTable> {
+    EMBEDDABLE_TABLES.iter().find(|table| table.name == name)
+}
+
+pub fn embeddable_table_names() -> Vec<&'static str> {
+    EMBEDDABLE_TABLES.iter().map(|table| table.name).collect()
+}
+
+#[cfg(test)]
+impl EmbeddableTable {
+    // A public table with only an id and a text column; tests set what they need on top
+    // of it with struct update syntax
+    pub fn test(name: &'static str) -> Self {
+        EmbeddableTable {
+            name,
+            id_column: "id",
+            owner_column: None,
+            text_column: "body",
+            text_template: None,
+            relations: &[],
+            file: None,
+            clean_html: false,
+            duplicates: None,
+            updated_at_column: "updated_at",
+            deleted_at_column: None,
+            visibility: Visibility::Public,
+            searched_by_default: true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sea_orm::{EntityTrait, IdenStatic, Iterable};
+
+    use super::*;
+    use crate::embed::document_text::placeholders;
+    use crate::entities::{
+        chat_messages, content_sources, contents, generated_texts, podcast_episodes,
+        uploaded_files, user_queries,
+    };
+
+    fn columns<E: EntityTrait>() -> Vec<String> {
+        E::Column::iter()
+            .map(|column| column.as_str().to_string())
+            .collect()
+    }
+
+    fn entity_columns(table: &str) -> Vec<String> {
+        match table {
+            "contents" => columns::<contents::Entity>(),
+            "content_sources" => columns::<content_sources::Entity>(),
+            "uploaded_files" => columns::<uploaded_files::Entity>(),
+            "chat_messages" => columns::<chat_messages::Entity>(),
+            "generated_texts" => columns::<generated_texts::Entity>(),
+            "podcast_episodes" => columns::<podcast_episodes::Entity>(),
+            "user_queries" => columns::<user_queries::Entity>(),
+            _ => panic!("no entity for table `{}`", table),
+        }
+    }
+
+    // The registry is only checked against the database when a query runs, so a column
+    // missing from the schema would otherwise go unnoticed until then
+    #[test]
+    fn registry_columns_exist_in_their_tables() {
+        for table in EMBEDDABLE_TABLES {
+            let mut used = vec![
+                (table.name, table.id_column),
+                (table.name, table.text_column),
+            ];
+            used.push((table.name, table.updated_at_column));
+            used.extend(table.owner_column.map(|column| (table.name, column)));
+            used.extend(table.deleted_at_column.map(|column| (table.name, column)));
+            if let Some(file) = &table.file {
+                used.push((table.name, file.path_column));
+                used.extend(file.mime_column.map(|column| (table.name, column)));
+            }
+            for relation in table.relations {
+                used.push((table.name, relation.foreign_key));
+                used.push((relation.table, relation.key));
+            }
+            for name in table.text_template.map(placeholders).unwrap_or_default() {
+                used.push(name.split_once('.').unwrap_or((table.name, name)));
+            }
+
+            for (table_name, column) in used {
+                assert!(
+                    entity_columns(table_name).iter().any(|name| name == column),
+                    "`{}.{}` in the `{}` entry doesn't exist",
+                    table_name,
+                    column,
+                    table.name
+                );
+            }
+        }
+    }
+}
@@ -41,7 +41,6 @@ pub mod sea_orm_active_enums;
 pub mod sessions;
 pub mod short_url_visits;
 pub mod short_urls;
-pub mod string_convert;
 pub mod subscription_items;
 pub mod subscriptions;
 pub mod team_invitations;
@@ -53,4 +52,7 @@ pub mod uploaded_files;
 pub mod usage_records;
 pub mod user_queries;
 pub mod users;
+pub mod vecembed_import_checkpoints;
+pub mod vecembed_minhash_bands;
+pub mod vecembed_sync_state;
 pub mod websockets_statistics_entries;
@@ -0,0 +1,25 @@
+//! `SeaORM` Entity for the table created by `src/migration`
+
+use sea_orm::entity::prelude::*;
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "vecembed_import_checkpoints")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: u64,
+    #[sea_orm(unique)]
+    pub table_name: String,
+    pub last_id: String,
+    pub processed_rows: u64,
+    pub total_rows: u64,
+    pub model: String,
+    pub settings_fingerprint: Option<String>,
+    pub started_at: DateTimeUtc,
+    pub updated_at: DateTimeUtc,
+    pub completed_at: Option<DateTimeUtc>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
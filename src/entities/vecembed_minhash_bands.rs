@@ -0,0 +1,19 @@
+//! `SeaORM` Entity for the table created by `src/migration`
+
+use sea_orm::entity::prelude::*;
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "vecembed_minhash_bands")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: u64,
+    pub table_name: String,
+    pub row_id: i64,
+    pub band: u8,
+    pub hash: u64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
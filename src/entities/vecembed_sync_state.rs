@@ -0,0 +1,28 @@
+//! `SeaORM` Entity for the table created by `src/migration`
+
+use sea_orm::entity::prelude::*;
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "vecembed_sync_state")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: u64,
+    pub table_name: String,
+    pub row_id: i64,
+    pub content_hash: Option<String>,
+    pub model: Option<String>,
+    pub settings_fingerprint: Option<String>,
+    pub synced_at: Option<DateTimeUtc>,
+    pub chunk_count: u32,
+    pub html_cleaned: bool,
+    #[sea_orm(column_type = "VarBinary(512)", nullable)]
+    pub minhash: Option<Vec<u8>>,
+    pub duplicate_of: Option<i64>,
+    #[sea_orm(column_type = "Text", nullable)]
+    pub last_error: Option<String>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
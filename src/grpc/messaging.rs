@@ -1,24 +1,43 @@
+use chrono::Utc;
 use qdrant_client::qdrant::{QuantizationSearchParams, SearchParams};
 use tonic::{Request, Response, Status};
 
 use crate::embed::create::create_and_save_embeddings;
 use crate::embed::errors::EmbeddingError;
-use crate::embed::get::get_documents;
+use crate::embed::get::{get_documents, recommend_documents, RetrievalOptions};
+use crate::embed::retry::is_retryable;
+use crate::embed::store::ScoredChunk;
 
 use crate::grpc::server::vecembed_rpc::vec_embed_rpc_server::VecEmbedRpc;
 use crate::grpc::server::vecembed_rpc::{
-    DocumentReply, DocumentsReply, RetrieveDocumentsRequest, StoreVectorEmbeddingReply,
+    DocumentReply, DocumentsReply, RecommendDocumentsRequest, RetrieveDocumentsRequest,
+    StoreVectorEmbeddingReply,
     StoreVectorEmbeddingRequest, StoreVectorEmbeddingsReply, StoreVectorEmbeddingsRequest,
 };
 
 impl From<EmbeddingError> for Status {
     fn from(err: EmbeddingError) -> Self {
         match err {
+            // Transient failures that outlasted the retries: the backend is down, try later
+            EmbeddingError::QdrantClient(_) if is_retryable(&err) => {
+                Status::unavailable(format!("Qdrant Client: {}", err))
+            }
             EmbeddingError::QdrantClient(_) => Status::internal(format!("Qdrant Client: {}", err)),
             EmbeddingError::DbError(_) => Status::internal(format!("DB Error: {}", err)),
+            EmbeddingError::OpenAIError(_) if is_retryable(&err) => {
+                Status::unavailable(format!("vLLM Server Error: {}", err))
+            }
             EmbeddingError::OpenAIError(_) => Status::internal(format!("vLLM Server Error: {}", err)),
             EmbeddingError::TokenizerError(_) => Status::internal(format!("{}", err)),
             EmbeddingError::TaskJoinError(_) => Status::internal(format!("Task Join: {}", err)),
+            EmbeddingError::DimensionMismatch { .. } => Status::failed_precondition(format!("{}", err)),
+            EmbeddingError::MissingVector { .. } => Status::failed_precondition(format!("{}", err)),
+            EmbeddingError::UnknownVector(_) => Status::invalid_argument(format!("{}", err)),
+            EmbeddingError::UnknownTable(_) => Status::invalid_argument(format!("{}", err)),
+            EmbeddingError::BackendUnavailable(_) => Status::unavailable(format!("{}", err)),
+            EmbeddingError::Extraction(_) => Status::failed_precondition(format!("{}", err)),
+            EmbeddingError::UnsupportedSetting(_) => Status::failed_precondition(format!("{}", err)),
+            EmbeddingError::EmbeddingCountMismatch { .. } => Status::internal(format!("{}", err)),
         }
     }
 }
@@ -32,9 +51,10 @@ impl VecEmbedRpc for VecEmbedService {
         &self,
         request: Request<StoreVectorEmbeddingRequest>,
     ) -> Result<Response<StoreVectorEmbeddingReply>, Status> {
+        let read_at = Utc::now();
         let req = request.into_inner();
         if let Some(document) = req.document {
-            let successful = create_and_save_embeddings(vec![document]).await?;
+            let successful = create_and_save_embeddings(vec![document], read_at).await?;
             let reply = StoreVectorEmbeddingReply { successful };
 
             return Ok(Response::new(reply));
@@ -53,58 +73,52 @@ impl VecEmbedRpc for VecEmbedService {
             &req.task_description,
             req.user_id,
             req.filter_ids,
-            req.limit,
-            proto_to_search_params(req.params),
+            &req.vector_name,
+            RetrievalOptions {
+                limit: req.limit,
+                params: proto_to_search_params(req.params),
+                languages: req.languages,
+                preferred_language: Some(req.preferred_language),
+                language_boost: req.language_boost,
+                collapse_duplicates: req.collapse_duplicates,
+            },
         )
         .await?
-        .result
         .into_iter()
-        .map(|scored_point| {
-            let table_name = scored_point
-                .payload
-                .get("table_name")
-                .unwrap()
-                .as_str()
-                .unwrap()
-                .to_string();
-
-            // Extracting the id and converting it to u64
-            let id = scored_point
-                .payload
-                .get("document_id")
-                .unwrap()
-                .clone()
-                .into_json()
-                .as_u64()
-                .unwrap();
-
-            let start = scored_point
-                .payload
-                .get("start")
-                .unwrap()
-                .clone()
-                .into_json()
-                .as_u64()
-                .unwrap();
-
-            let end = scored_point
-                .payload
-                .get("end")
-                .unwrap()
-                .clone()
-                .into_json()
-                .as_u64()
-                .unwrap();
-
-            DocumentReply {
-                table_name,
-                id,
-                user_id: req.user_id,
-                ranking_score: scored_point.score,
-                start,
-                end,
-            }
-        })
+        .map(|scored_chunk| document_reply(scored_chunk, req.user_id))
+        .collect();
+
+        let reply = DocumentsReply { documents };
+        Ok(Response::new(reply))
+    }
+
+    async fn recommend_documents(
+        &self,
+        request: Request<RecommendDocumentsRequest>,
+    ) -> Result<Response<DocumentsReply>, Status> {
+        let req = request.into_inner();
+        if req.positive.is_empty() {
+            return Err(Status::invalid_argument("No positive documents provided."));
+        }
+
+        let documents = recommend_documents(
+            &req.positive,
+            &req.negative,
+            req.user_id,
+            req.filter_ids,
+            &req.vector_name,
+            RetrievalOptions {
+                limit: req.limit,
+                params: proto_to_search_params(req.params),
+                languages: req.languages,
+                preferred_language: Some(req.preferred_language),
+                language_boost: req.language_boost,
+                collapse_duplicates: req.collapse_duplicates,
+            },
+        )
+        .await?
+        .into_iter()
+        .map(|scored_chunk| document_reply(scored_chunk, req.user_id))
         .collect();
 
         let reply = DocumentsReply { documents };
@@ -115,9 +129,10 @@ impl VecEmbedRpc for VecEmbedService {
         &self,
         request: Request<StoreVectorEmbeddingsRequest>,
     ) -> Result<Response<StoreVectorEmbeddingsReply>, Status> {
+        let read_at = Utc::now();
         let req = request.into_inner();
         if !req.documents.is_empty() {
-            let successful = create_and_save_embeddings(req.documents).await?;
+            let successful = create_and_save_embeddings(req.documents, read_at).await?;
             let reply = StoreVectorEmbeddingsReply { successful };
 
             return Ok(Response::new(reply));
@@ -127,6 +142,79 @@ impl VecEmbedRpc for VecEmbedService {
     }
 }
 
+fn document_reply(scored_chunk: ScoredChunk, user_id: i64) -> DocumentReply {
+    let table_name = scored_chunk
+        .payload
+        .get("table_name")
+        .and_then(|value| value.as_str())
+        .unwrap()
+        .to_string();
+
+    // Extracting the id and converting it to u64
+    let id = scored_chunk
+        .payload
+        .get("document_id")
+        .and_then(|value| value.as_u64())
+        .unwrap();
+
+    let start = scored_chunk
+        .payload
+        .get("start")
+        .and_then(|value| value.as_u64())
+        .unwrap();
+
+    let end = scored_chunk
+        .payload
+        .get("end")
+        .and_then(|value| value.as_u64())
+        .unwrap();
+
+    let payload_u32 = |key: &str| {
+        scored_chunk
+            .payload
+            .get(key)
+            .and_then(|value| value.as_u64())
+            .map(|value| value as u32)
+    };
+
+    let heading_path = scored_chunk
+        .payload
+        .get("heading_path")
+        .and_then(|value| value.as_array())
+        .map(|titles| {
+            titles
+                .iter()
+                .filter_map(|title| title.as_str().map(str::to_string))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    DocumentReply {
+        table_name,
+        id,
+        user_id,
+        ranking_score: scored_chunk.score,
+        start,
+        end,
+        chunk_index: payload_u32("chunk_index").unwrap_or_default(),
+        token_count: payload_u32("token_count").unwrap_or_default(),
+        page: payload_u32("page"),
+        page_end: payload_u32("page_end"),
+        heading_path,
+        paragraph: payload_u32("paragraph").unwrap_or_default(),
+        language: scored_chunk
+            .payload
+            .get("language")
+            .and_then(|value| value.as_str())
+            .unwrap_or_default()
+            .to_string(),
+        duplicate_of: scored_chunk
+            .payload
+            .get("duplicate_of")
+            .and_then(|value| value.as_u64()),
+    }
+}
+
 impl From<QuantizationSearchParams>
     for crate::grpc::server::vecembed_rpc::QuantizationSearchParams
 {
@@ -152,7 +240,7 @@ fn proto_to_quantization_search_params(
 fn proto_to_search_params(
     proto_params: Option<crate::grpc::server::vecembed_rpc::SearchParams>,
 ) -> Option<SearchParams> {
-    log::info!("hnsw_ef {:?}", proto_params.clone().unwrap().hnsw_ef);
+    log::info!("hnsw_ef {:?}", proto_params.as_ref().and_then(|params| params.hnsw_ef));
     proto_params.map(|params| SearchParams {
         hnsw_ef: params.hnsw_ef,
         exact: params.exact,
@@ -160,3 +248,40 @@ fn proto_to_search_params(
         indexed_only: params.indexed_only,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use async_openai::error::{ApiError, OpenAIError};
+    use tonic::Code;
+
+    use super::*;
+    use crate::embed::errors::QdrantClientError;
+
+    fn api_error(error_type: Option<&str>) -> EmbeddingError {
+        EmbeddingError::OpenAIError(OpenAIError::ApiError(ApiError {
+            message: "failed".to_string(),
+            r#type: error_type.map(str::to_string),
+            param: None,
+            code: None,
+        }))
+    }
+
+    #[test]
+    fn transient_backend_failures_are_unavailable() {
+        let qdrant = EmbeddingError::QdrantClient(QdrantClientError::Unavailable("down".to_string()));
+
+        assert_eq!(Status::from(qdrant).code(), Code::Unavailable);
+        assert_eq!(Status::from(api_error(None)).code(), Code::Unavailable);
+    }
+
+    #[test]
+    fn rejected_requests_stay_internal() {
+        let qdrant = EmbeddingError::QdrantClient(QdrantClientError::ClientError("bad".to_string()));
+
+        assert_eq!(Status::from(qdrant).code(), Code::Internal);
+        assert_eq!(
+            Status::from(api_error(Some("invalid_request_error"))).code(),
+            Code::Internal
+        );
+    }
+}
@@ -1,25 +1,59 @@
+mod config;
 mod embed;
 mod entities;
 mod grpc;
 mod logger;
+mod migration;
 
 use clap::Parser;
 use futures::executor::block_on;
-use log::warn;
+use log::{info, warn};
+use sea_orm_migration::MigratorTrait;
 
 use crate::{
-    entities::string_convert::dynamic_import_embeddings, grpc::server::start_grpc_server,
+    embed::{
+        collections::sync_collections,
+        import::{estimate_import, import_table, lookup_table, CheckpointMode, ImportOptions},
+        instances::get_db_instance,
+        reindex::{estimate_reindex, reindex, rollback},
+        sync_state::embedding_fingerprint,
+    },
+    grpc::server::start_grpc_server,
     logger::get_logger_instance,
+    migration::Migrator,
 };
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+    /// Embed the stale rows of a table registered in `src/embed/tables.rs`
     #[arg(short, long)]
     import: Option<String>,
 
+    /// Start the import at this ID instead of where an unfinished import left off
     #[arg(short, long)]
     start: Option<u64>,
+
+    /// Resume the unfinished import of the table, failing if there's none
+    #[arg(long, requires = "import", conflicts_with_all = ["start", "dry_run"])]
+    resume: bool,
+
+    /// Forget the unfinished import of the table and start over
+    #[arg(long, requires = "import", conflicts_with_all = ["start", "resume", "dry_run"])]
+    reset: bool,
+
+    /// Rebuild every table into a new collection version, then switch the alias to it
+    #[arg(long)]
+    reindex: bool,
+
+    /// With --import or --reindex, report how many chunks and tokens it would embed,
+    /// without calling the embedding server or writing to the vector store
+    #[arg(long, conflicts_with = "rollback")]
+    dry_run: bool,
+
+    /// Switch the alias back to the previous collection version
+    #[arg(long, conflicts_with = "reindex")]
+    rollback: bool,
 }
 
 #[tokio::main]
@@ -44,8 +78,57 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let args = Args::parse();
 
+    // Dry runs only read the source tables, and VecEmbed's own without creating them
+    if args.dry_run {
+        if !Migrator::get_pending_migrations(get_db_instance().await).await?.is_empty() {
+            return Err("--dry-run needs VecEmbed's tables; run it once without --dry-run to create them".into());
+        }
+
+        if args.reindex {
+            estimate_reindex().await?;
+        } else if let Some(import) = args.import.as_deref() {
+            let options = ImportOptions {
+                start_from: args.start,
+                ..Default::default()
+            };
+            estimate_import(lookup_table(import)?, &options).await?;
+        } else {
+            return Err("--dry-run needs --import or --reindex".into());
+        }
+        return Ok(());
+    }
+
+    // Create VecEmbed's own tables, e.g. the sync state
+    Migrator::up(get_db_instance().await, None).await?;
+    info!("Embedding settings fingerprint: {}", embedding_fingerprint());
+
+    // Apply the collection config before anything reads or writes vectors
+    sync_collections().await?;
+
+    if args.reindex {
+        reindex().await?;
+        return Ok(());
+    }
+
+    if args.rollback {
+        rollback().await?;
+        return Ok(());
+    }
+
     if let Some(import) = args.import.as_deref() {
-        dynamic_import_embeddings(import, args.start).await?;
+        let checkpoint = if args.resume {
+            CheckpointMode::Resume
+        } else if args.reset || args.start.is_some() {
+            CheckpointMode::Reset
+        } else {
+            CheckpointMode::Auto
+        };
+        let options = ImportOptions {
+            start_from: args.start,
+            checkpoint,
+            ..Default::default()
+        };
+        import_table(import, &options).await?;
         return Ok(());
     }
 
@@ -56,3 +139,18 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dry_run_refuses_checkpoint_flags() {
+        for flag in ["--resume", "--reset"] {
+            let args = Args::try_parse_from(["vecembed", "--import=contents", flag, "--dry-run"]);
+            assert!(args.is_err(), "{}", flag);
+        }
+        let args = Args::try_parse_from(["vecembed", "--import=contents", "--start=10", "--dry-run"]);
+        assert!(args.is_ok());
+    }
+}
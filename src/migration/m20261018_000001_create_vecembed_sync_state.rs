@@ -0,0 +1,68 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(VecembedSyncState::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(VecembedSyncState::Id)
+                            .big_unsigned()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(VecembedSyncState::TableName).string_len(64).not_null())
+                    .col(ColumnDef::new(VecembedSyncState::RowId).big_integer().not_null())
+                    .col(ColumnDef::new(VecembedSyncState::ContentHash).char_len(64).null())
+                    .col(ColumnDef::new(VecembedSyncState::Model).string().null())
+                    .col(ColumnDef::new(VecembedSyncState::SyncedAt).timestamp().null())
+                    .col(
+                        ColumnDef::new(VecembedSyncState::ChunkCount)
+                            .unsigned()
+                            .not_null()
+                            .default(0),
+                    )
+                    .col(ColumnDef::new(VecembedSyncState::LastError).text().null())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("vecembed_sync_state_row")
+                    .table(VecembedSyncState::Table)
+                    .col(VecembedSyncState::TableName)
+                    .col(VecembedSyncState::RowId)
+                    .unique()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(VecembedSyncState::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum VecembedSyncState {
+    Table,
+    Id,
+    TableName,
+    RowId,
+    ContentHash,
+    Model,
+    SyncedAt,
+    ChunkCount,
+    LastError,
+}
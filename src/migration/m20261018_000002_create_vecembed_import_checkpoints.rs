@@ -0,0 +1,66 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(VecembedImportCheckpoints::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(VecembedImportCheckpoints::Id)
+                            .big_unsigned()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(VecembedImportCheckpoints::TableName)
+                            .string_len(64)
+                            .not_null()
+                            .unique_key(),
+                    )
+                    .col(ColumnDef::new(VecembedImportCheckpoints::LastId).string_len(64).not_null())
+                    .col(
+                        ColumnDef::new(VecembedImportCheckpoints::ProcessedRows)
+                            .big_unsigned()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(VecembedImportCheckpoints::TotalRows)
+                            .big_unsigned()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(VecembedImportCheckpoints::Model).string().not_null())
+                    .col(ColumnDef::new(VecembedImportCheckpoints::StartedAt).timestamp().not_null())
+                    .col(ColumnDef::new(VecembedImportCheckpoints::UpdatedAt).timestamp().not_null())
+                    .col(ColumnDef::new(VecembedImportCheckpoints::CompletedAt).timestamp().null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(VecembedImportCheckpoints::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum VecembedImportCheckpoints {
+    Table,
+    Id,
+    TableName,
+    LastId,
+    ProcessedRows,
+    TotalRows,
+    Model,
+    StartedAt,
+    UpdatedAt,
+    CompletedAt,
+}
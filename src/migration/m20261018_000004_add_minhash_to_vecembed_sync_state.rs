@@ -0,0 +1,95 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(VecembedSyncState::Table)
+                    .add_column(ColumnDef::new(VecembedSyncState::Minhash).var_binary(512).null())
+                    .add_column(ColumnDef::new(VecembedSyncState::DuplicateOf).big_integer().null())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(VecembedMinhashBands::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(VecembedMinhashBands::Id)
+                            .big_unsigned()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(VecembedMinhashBands::TableName).string_len(64).not_null())
+                    .col(ColumnDef::new(VecembedMinhashBands::RowId).big_integer().not_null())
+                    .col(ColumnDef::new(VecembedMinhashBands::Band).tiny_unsigned().not_null())
+                    .col(ColumnDef::new(VecembedMinhashBands::Hash).big_unsigned().not_null())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("vecembed_minhash_bands_hash")
+                    .table(VecembedMinhashBands::Table)
+                    .col(VecembedMinhashBands::TableName)
+                    .col(VecembedMinhashBands::Band)
+                    .col(VecembedMinhashBands::Hash)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("vecembed_minhash_bands_row")
+                    .table(VecembedMinhashBands::Table)
+                    .col(VecembedMinhashBands::TableName)
+                    .col(VecembedMinhashBands::RowId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(VecembedMinhashBands::Table).to_owned())
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(VecembedSyncState::Table)
+                    .drop_column(VecembedSyncState::Minhash)
+                    .drop_column(VecembedSyncState::DuplicateOf)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum VecembedSyncState {
+    Table,
+    Minhash,
+    DuplicateOf,
+}
+
+#[derive(DeriveIden)]
+enum VecembedMinhashBands {
+    Table,
+    Id,
+    TableName,
+    RowId,
+    Band,
+    Hash,
+}
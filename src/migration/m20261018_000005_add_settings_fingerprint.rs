@@ -0,0 +1,67 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(VecembedSyncState::Table)
+                    .add_column(
+                        ColumnDef::new(VecembedSyncState::SettingsFingerprint)
+                            .char_len(64)
+                            .null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(VecembedImportCheckpoints::Table)
+                    .add_column(
+                        ColumnDef::new(VecembedImportCheckpoints::SettingsFingerprint)
+                            .char_len(64)
+                            .null(),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(VecembedImportCheckpoints::Table)
+                    .drop_column(VecembedImportCheckpoints::SettingsFingerprint)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(VecembedSyncState::Table)
+                    .drop_column(VecembedSyncState::SettingsFingerprint)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum VecembedSyncState {
+    Table,
+    SettingsFingerprint,
+}
+
+#[derive(DeriveIden)]
+enum VecembedImportCheckpoints {
+    Table,
+    SettingsFingerprint,
+}
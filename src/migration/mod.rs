@@ -0,0 +1,24 @@
+use sea_orm_migration::prelude::*;
+
+mod m20261018_000001_create_vecembed_sync_state;
+mod m20261018_000002_create_vecembed_import_checkpoints;
+mod m20261018_000003_add_html_cleaned_to_vecembed_sync_state;
+mod m20261018_000004_add_minhash_to_vecembed_sync_state;
+mod m20261018_000005_add_settings_fingerprint;
+
+// VecEmbed's own tables. Applied on startup and tracked in `seaql_migrations`, apart
+// from the application's migrations.
+pub struct Migrator;
+
+#[async_trait::async_trait]
+impl MigratorTrait for Migrator {
+    fn migrations() -> Vec<Box<dyn MigrationTrait>> {
+        vec![
+            Box::new(m20261018_000001_create_vecembed_sync_state::Migration),
+            Box::new(m20261018_000002_create_vecembed_import_checkpoints::Migration),
+            Box::new(m20261018_000003_add_html_cleaned_to_vecembed_sync_state::Migration),
+            Box::new(m20261018_000004_add_minhash_to_vecembed_sync_state::Migration),
+            Box::new(m20261018_000005_add_settings_fingerprint::Migration),
+        ]
+    }
+}